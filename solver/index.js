@@ -1,5 +1,6 @@
 const AWS = require("aws-sdk");
 const child_process = require('child_process');
+const fs = require('fs');
 
 AWS.config.update({
     region: 'ap-northeast-1'
@@ -31,6 +32,7 @@ exports.handler = async function (event, context) {
     const commitAndParams = `${commitHash}:${Object.keys(params).sort().map(key => `${key}=${params[key]}`).join("&")}`
 
     const SOLVER_PATH = '/code/target/release/icfpc2021';
+    const STATS_PATH = `/tmp/stats-${ProblemId}.json`;
 
     const item = await client.get({
         TableName: ProblemsTableName,
@@ -46,9 +48,12 @@ exports.handler = async function (event, context) {
         input: JSON.stringify(problem),
         env: {
             ...event.env,
+            STATS_OUTPUT: STATS_PATH,
             RUST_BACKTRACE: '1'
         }
     }));
+    const stats = JSON.parse(fs.readFileSync(STATS_PATH));
+    console.log(`Stats = ${JSON.stringify(stats)}`);
     const dislikes = calculateDislikes(problem, solution);
     console.log(`Dislikes = ${dislikes}, solution = ${JSON.stringify(solution)}`);
 
@@ -60,6 +65,7 @@ exports.handler = async function (event, context) {
             Dislikes: dislikes,
             Pose: solution,
             UnlockBonuses: [], // TODO
+            Stats: stats,
         },
         ConditionExpression: "attribute_not_exists(ProblemId)",
     }
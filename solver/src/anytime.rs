@@ -0,0 +1,85 @@
+use crate::common::*;
+use crate::constraints::Constraints;
+use crate::inout::*;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// 探索中に見つかった妥当なポーズのうち、dislike が真に小さくなったものをその都度ファイルに書き出す。
+// 長い焼きなましの途中経過を外から見たり、途中で落ちても最後の改善までは残るようにするため
+struct AnytimeWriter {
+    path: PathBuf,
+    input: Input,
+    used_bonus_types: Vec<BonusType>,
+    constraints: Constraints,
+    best_dislike: f64,
+}
+
+static WRITER: Mutex<Option<AnytimeWriter>> = Mutex::new(None);
+
+// 書き出しを有効にする。妥当性の判定には元の問題を使うので、
+// orthgonal のように figure.vertices を差し替えた Input を渡されても正しく判定できる
+pub fn install(
+    path: &Path,
+    input: &Input,
+    used_bonus_types: &[BonusType],
+    constraints: &Constraints,
+) {
+    *WRITER.lock().unwrap() = Some(AnytimeWriter {
+        path: path.to_path_buf(),
+        input: input.clone(),
+        used_bonus_types: used_bonus_types.to_vec(),
+        constraints: constraints.clone(),
+        best_dislike: f64::INFINITY,
+    });
+}
+
+// 書き出しが有効で、vertices がこれまでに書き出したものより良い妥当なポーズなら書き出す
+pub fn offer(vertices: &[Point]) {
+    let mut writer = WRITER.lock().unwrap();
+    let writer = match writer.as_mut() {
+        Some(writer) => writer,
+        None => return,
+    };
+    if vertices.len() != writer.input.figure.vertices.len() {
+        return;
+    }
+    let dislike = calculate_dislike(vertices, &writer.input.hole);
+    if dislike >= writer.best_dislike || !writer.constraints.is_satisfied(vertices) {
+        return;
+    }
+    if !does_valid_pose(
+        &vertices.to_vec(),
+        &writer.input.figure,
+        &writer.input.hole,
+        writer.input.epsilon,
+        &writer.used_bonus_types,
+        None,
+    ) {
+        return;
+    }
+    writer.best_dislike = dislike;
+    let j = vertices_to_pose_json(vertices, &writer.used_bonus_types, &None);
+    write_file_atomically(&writer.path, &j);
+}
+
+#[test]
+fn test_offer() {
+    let input = parse_input(
+        r#"{"hole":[[0,0],[20,0],[20,20],[0,20]],"epsilon":0,"figure":{"edges":[[0,1]],"vertices":[[0,0],[4,0]]},"bonuses":[]}"#,
+    );
+    let path = std::env::temp_dir().join(format!("anytime-test-{}.json", std::process::id()));
+    install(&path, &input, &[], &Constraints::default());
+    let read = || pose_json_to_vertices(&load_pose_json(&path));
+    offer(&[Point::new(0.0, 0.0), Point::new(4.0, 0.0)]);
+    assert_eq!(read()[1], Point::new(4.0, 0.0));
+    // 辺の長さが変わっているので書き出さない
+    offer(&[Point::new(0.0, 20.0), Point::new(20.0, 20.0)]);
+    assert_eq!(read()[1], Point::new(4.0, 0.0));
+    offer(&[Point::new(8.0, 10.0), Point::new(12.0, 10.0)]);
+    assert_eq!(read()[1], Point::new(12.0, 10.0));
+    // dislike が大きくなるものは書き出さない
+    offer(&[Point::new(0.0, 0.0), Point::new(4.0, 0.0)]);
+    assert_eq!(read()[1], Point::new(12.0, 10.0));
+    *WRITER.lock().unwrap() = None;
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,282 @@
+use crate::common::*;
+use crate::inout::*;
+use std::collections::BTreeSet;
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuditStatus {
+    Ok,
+    Invalid(String),
+    // 問題はあるが解がない
+    MissingSolution,
+    // 解はあるが問題がない
+    MissingProblem,
+    // solutions.jsonl に記録されている dislike と再計算した値が違う
+    Mismatch(String),
+}
+
+impl AuditStatus {
+    pub fn is_failure(&self) -> bool {
+        *self != AuditStatus::Ok
+    }
+
+    fn label(&self) -> &str {
+        match self {
+            AuditStatus::Ok => "ok",
+            AuditStatus::Invalid(_) => "invalid",
+            AuditStatus::MissingSolution => "missing solution",
+            AuditStatus::MissingProblem => "missing problem",
+            AuditStatus::Mismatch(_) => "mismatch",
+        }
+    }
+
+    fn note(&self) -> &str {
+        match self {
+            AuditStatus::Invalid(note) | AuditStatus::Mismatch(note) => note,
+            _ => "",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AuditEntry {
+    pub id: u32,
+    pub status: AuditStatus,
+    pub dislike: Option<f64>,
+}
+
+pub fn parse_bonus_type(s: &str) -> Option<BonusType> {
+    match s {
+        "GLOBALIST" => Some(BonusType::Globalist),
+        "BREAK_A_LEG" => Some(BonusType::BreakALeg),
+        "WALLHACK" => Some(BonusType::WallHack),
+        _ => None,
+    }
+}
+
+// ボーナスの使い方を確かめ、ポーズを検証するための (使うボーナス, 折る辺) を返す
+fn check_bonuses(
+    id: u32,
+    pose_json: &PoseJSON,
+    input: &Input,
+    problems_dir: &Path,
+) -> Result<(Vec<BonusType>, Option<Edge>), String> {
+    let bonuses = match &pose_json.bonuses {
+        Some(bonuses) => bonuses,
+        None => return Ok((vec![], None)),
+    };
+    if bonuses.len() > 1 {
+        return Err(format!("uses {} bonuses", bonuses.len()));
+    }
+    let mut used_bonus_types = vec![];
+    let mut break_leg = None;
+    for b in bonuses.iter() {
+        let bonus_type =
+            parse_bonus_type(&b.bonus).ok_or_else(|| format!("unsupported bonus {}", b.bonus))?;
+        // ボーナスは別の問題で手に入れたものなので、その問題にこの問題向けのボーナスがあるか確かめる
+        let source_path = problems_dir.join(format!("{}.problem", b.problem));
+        if !source_path.exists() {
+            return Err(format!("bonus source problem {} does not exist", b.problem));
+        }
+        let source = load_input(&source_path);
+        if !source
+            .bonuses
+            .iter()
+            .any(|sb| sb.bonus == b.bonus && sb.problem == id as i64)
+        {
+            return Err(format!(
+                "problem {} has no {} bonus for problem {}",
+                b.problem, b.bonus, id
+            ));
+        }
+        if bonus_type == BonusType::BreakALeg {
+            let edge = match &b.edge {
+                Some(edge) if edge.len() == 2 => Edge::new(edge[0] as usize, edge[1] as usize),
+                _ => return Err("BREAK_A_LEG without an edge".to_string()),
+            };
+            let exists = input
+                .figure
+                .edges
+                .iter()
+                .any(|e| *e == edge || *e == Edge::new(edge.w, edge.v));
+            if !exists {
+                return Err(format!("BREAK_A_LEG edge {:?} is not in the figure", edge));
+            }
+            break_leg = Some(edge);
+        }
+        used_bonus_types.push(bonus_type);
+    }
+    Ok((used_bonus_types, break_leg))
+}
+
+// ボーナスも含めてポーズを検証し、妥当なら dislike を返す
+pub fn check_pose(
+    id: u32,
+    pose_json: &PoseJSON,
+    input: &Input,
+    problems_dir: &Path,
+) -> Result<f64, String> {
+    let (used_bonus_types, break_leg) = check_bonuses(id, pose_json, input, problems_dir)?;
+    let vertices = pose_json_to_vertices(pose_json);
+    let n = input.figure.vertices.len() + if break_leg.is_some() { 1 } else { 0 };
+    if vertices.len() != n {
+        return Err(format!("has {} vertices, expected {}", vertices.len(), n));
+    }
+    // 折る辺は問題の向きに揃えてから渡す
+    let break_leg = break_leg.map(|edge| {
+        *input
+            .figure
+            .edges
+            .iter()
+            .find(|e| **e == edge || **e == Edge::new(edge.w, edge.v))
+            .unwrap()
+    });
+    if !does_valid_pose(
+        &vertices,
+        &input.figure,
+        &input.hole,
+        input.epsilon,
+        &used_bonus_types,
+        break_leg,
+    ) {
+        return Err("pose is invalid".to_string());
+    }
+    Ok(calculate_dislike(&vertices, &input.hole))
+}
+
+// 読めないファイルは妥当でないものとして扱う
+pub fn try_load_pose_json(path: &Path) -> Result<PoseJSON, String> {
+    std::fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|s| serde_json::from_str(&s).map_err(|e| e.to_string()))
+        .map_err(|e| format!("can't load: {}", e))
+}
+
+pub fn audit_solution(
+    id: u32,
+    problem_path: &Path,
+    solution_path: &Path,
+    problems_dir: &Path,
+    recorded: &[SolutionRecordJSON],
+) -> AuditEntry {
+    let input = load_input(problem_path);
+    let checked = try_load_pose_json(solution_path).and_then(|pose_json| {
+        check_pose(id, &pose_json, &input, problems_dir).map(|dislike| (pose_json, dislike))
+    });
+    let (pose_json, dislike) = match checked {
+        Ok(ret) => ret,
+        Err(note) => {
+            return AuditEntry {
+                id,
+                status: AuditStatus::Invalid(note),
+                dislike: None,
+            }
+        }
+    };
+    let status = recorded
+        .iter()
+        .filter(|r| r.problem_id == id.to_string() && r.pose.vertices == pose_json.vertices)
+        .find(|r| r.dislikes as f64 != dislike)
+        .map(|r| {
+            AuditStatus::Mismatch(format!(
+                "recorded dislike is {}, but actually {}",
+                r.dislikes, dislike
+            ))
+        })
+        .unwrap_or(AuditStatus::Ok);
+    AuditEntry {
+        id,
+        status,
+        dislike: Some(dislike),
+    }
+}
+
+pub fn list_ids(dir: &Path, extension: &str) -> BTreeSet<u32> {
+    std::fs::read_dir(dir)
+        .unwrap_or_else(|e| panic!("can't read {}: {}", dir.display(), e))
+        .filter_map(|entry| {
+            let path = entry.unwrap().path();
+            if path.extension()? != extension {
+                return None;
+            }
+            path.file_stem()?.to_str()?.parse().ok()
+        })
+        .collect()
+}
+
+// problems_dir の全ての問題と solutions_dir の全ての解を突き合わせる
+pub fn audit_all(
+    problems_dir: &Path,
+    solutions_dir: &Path,
+    recorded: &[SolutionRecordJSON],
+) -> Vec<AuditEntry> {
+    let problem_ids = list_ids(problems_dir, "problem");
+    let solution_ids = list_ids(solutions_dir, "solution");
+    problem_ids
+        .union(&solution_ids)
+        .map(|&id| {
+            let problem_path = problems_dir.join(format!("{}.problem", id));
+            let solution_path = solutions_dir.join(format!("{}.solution", id));
+            if !problem_ids.contains(&id) {
+                AuditEntry {
+                    id,
+                    status: AuditStatus::MissingProblem,
+                    dislike: None,
+                }
+            } else if !solution_ids.contains(&id) {
+                AuditEntry {
+                    id,
+                    status: AuditStatus::MissingSolution,
+                    dislike: None,
+                }
+            } else {
+                audit_solution(id, &problem_path, &solution_path, problems_dir, recorded)
+            }
+        })
+        .collect()
+}
+
+pub fn print_entries(entries: &[AuditEntry]) {
+    println!("{:>5}  {:<16} {:>10}  note", "id", "status", "dislike");
+    for entry in entries.iter().filter(|e| e.status.is_failure()) {
+        let dislike = entry
+            .dislike
+            .map(|d| d.to_string())
+            .unwrap_or_else(|| "-".to_string());
+        println!(
+            "{:>5}  {:<16} {:>10}  {}",
+            entry.id,
+            entry.status.label(),
+            dislike,
+            entry.status.note()
+        );
+    }
+}
+
+#[test]
+fn test_audit_solution() {
+    let dir = std::env::temp_dir().join(format!("audit-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("1.problem"),
+        r#"{"hole":[[0,0],[20,0],[20,20],[0,20]],"epsilon":0,"figure":{"edges":[[0,1]],"vertices":[[0,0],[4,0]]},"bonuses":[]}"#,
+    )
+    .unwrap();
+    std::fs::write(dir.join("1.solution"), r#"{"vertices":[[0,0],[4,0]]}"#).unwrap();
+    std::fs::write(
+        dir.join("2.solution"),
+        r#"{"vertices":[[0,0],[0,4]],"bonuses":[{"bonus":"GLOBALIST","problem":3}]}"#,
+    )
+    .unwrap();
+    std::fs::copy(dir.join("1.problem"), dir.join("2.problem")).unwrap();
+    std::fs::copy(dir.join("1.problem"), dir.join("4.problem")).unwrap();
+    let recorded: Vec<SolutionRecordJSON> = vec![serde_json::from_str(
+        r#"{"ProblemId":"1","Dislikes":100,"Pose":{"vertices":[[0,0],[4,0]]}}"#,
+    )
+    .unwrap()];
+    let entries = audit_all(&dir, &dir, &recorded);
+    let statuses: Vec<&str> = entries.iter().map(|e| e.status.label()).collect();
+    assert_eq!(statuses, vec!["mismatch", "invalid", "missing solution"]);
+    assert_eq!(entries[0].dislike, Some(1312.0));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,340 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::Instant;
+
+// 名前付きのパイプライン。solve に渡す環境変数の組で表す。
+// ここにない組み合わせは、benchmark を実行するときの環境変数でそのまま指定できる
+const PIPELINES: [(&str, &[(&str, &str)]); 7] = [
+    ("default", &[]),
+    ("annealing3", &[("ANNEALING_SOLVER", "annealing3")]),
+    ("hill_climbing", &[("ANNEALING_SOLVER", "hill_climbing")]),
+    (
+        "penalty_annealing",
+        &[("ANNEALING_SOLVER", "penalty_annealing")],
+    ),
+    ("dfs2", &[("INITIAL_SOLVER", "dfs2")]),
+    (
+        "dfs2_annealing3",
+        &[
+            ("INITIAL_SOLVER", "dfs2"),
+            ("ANNEALING_SOLVER", "annealing3"),
+        ],
+    ),
+    ("skip_ortho", &[("SKIP_ORTHO", "1")]),
+];
+
+pub fn pipeline_envs(name: &str) -> &'static [(&'static str, &'static str)] {
+    PIPELINES
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, envs)| *envs)
+        .unwrap_or_else(|| {
+            let names: Vec<&str> = PIPELINES.iter().map(|(n, _)| *n).collect();
+            panic!(
+                "Unknown pipeline {} (available: {})",
+                name,
+                names.join(", ")
+            )
+        })
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BenchmarkRun {
+    pub problem: u32,
+    pub seed: u64,
+    pub iterations: u64,
+    // 解が得られなかったときは None
+    pub dislike: Option<f64>,
+    pub valid: bool,
+    pub elapsed: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BenchmarkResult {
+    pub pipeline: String,
+    pub runs: Vec<BenchmarkRun>,
+}
+
+// "1-10,30" のような指定を問題番号の列にする
+pub fn parse_ids(s: &str) -> Vec<u32> {
+    let mut ids = vec![];
+    for item in s.split(',').filter(|item| !item.is_empty()) {
+        let parse = |x: &str| -> u32 {
+            x.trim()
+                .parse()
+                .unwrap_or_else(|_| panic!("Invalid problem id {}", item))
+        };
+        match item.split_once('-') {
+            Some((from, to)) => ids.extend(parse(from)..=parse(to)),
+            None => ids.push(parse(item)),
+        }
+    }
+    ids
+}
+
+// 自分自身を solve として別プロセスで実行し、STATS_OUTPUT に書かれた結果を読む
+fn run_once(
+    pipeline: &str,
+    problem_path: &Path,
+    seed: u64,
+    iterations: u64,
+) -> (Option<f64>, bool) {
+    let stats_path =
+        std::env::temp_dir().join(format!("benchmark-stats-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&stats_path);
+    let problem = std::fs::File::open(problem_path)
+        .unwrap_or_else(|e| panic!("can't open {}: {}", problem_path.display(), e));
+    let status = Command::new(std::env::current_exe().unwrap())
+        .envs(pipeline_envs(pipeline).iter().copied())
+        .env("FIX_SEED", "1")
+        .env("SEED", seed.to_string())
+        .env("ITERATION_LIMIT", iterations.to_string())
+        .env("STATS_OUTPUT", &stats_path)
+        .env_remove("INITIAL_SOLUTION")
+        .env_remove("ANYTIME_OUTPUT")
+        .env_remove("TRACE_OUTPUT")
+        .env_remove("RENDER_OUTPUT")
+        .stdin(problem)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .expect("failed to run solver");
+    let summary: Option<serde_json::Value> = std::fs::read_to_string(&stats_path)
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok());
+    let _ = std::fs::remove_file(&stats_path);
+    match summary {
+        Some(summary) => (
+            summary["dislike"].as_f64(),
+            status.success() && summary["valid"].as_bool() == Some(true),
+        ),
+        None => (None, false),
+    }
+}
+
+pub fn run(
+    pipeline: &str,
+    problems_dir: &Path,
+    ids: &[u32],
+    seeds: &[u64],
+    iteration_limits: &[u64],
+) -> BenchmarkResult {
+    let mut runs = vec![];
+    for &problem in ids.iter() {
+        let problem_path = problems_dir.join(format!("{}.problem", problem));
+        if !problem_path.exists() {
+            eprintln!("{} does not exist", problem_path.display());
+            continue;
+        }
+        for &iterations in iteration_limits.iter() {
+            for &seed in seeds.iter() {
+                let start_at = Instant::now();
+                let (dislike, valid) = run_once(pipeline, &problem_path, seed, iterations);
+                let run = BenchmarkRun {
+                    problem,
+                    seed,
+                    iterations,
+                    dislike,
+                    valid,
+                    elapsed: start_at.elapsed().as_secs_f64(),
+                };
+                eprintln!(
+                    "problem {} iterations {} seed {}: dislike = {:?}, valid = {}, {:.2}s",
+                    problem, iterations, seed, run.dislike, run.valid, run.elapsed
+                );
+                runs.push(run);
+            }
+        }
+    }
+    BenchmarkResult {
+        pipeline: pipeline.to_string(),
+        runs,
+    }
+}
+
+// 同じ問題・同じ反復回数の実行をシードについてまとめたもの
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GroupScore {
+    pub runs: usize,
+    pub valid_runs: usize,
+    // 妥当な実行の dislike の平均
+    pub mean_dislike: Option<f64>,
+    pub mean_elapsed: f64,
+}
+
+fn summarize(runs: &[BenchmarkRun]) -> BTreeMap<(u32, u64), GroupScore> {
+    let mut groups: BTreeMap<(u32, u64), Vec<&BenchmarkRun>> = BTreeMap::new();
+    for run in runs.iter() {
+        groups
+            .entry((run.problem, run.iterations))
+            .or_default()
+            .push(run);
+    }
+    groups
+        .into_iter()
+        .map(|(key, runs)| {
+            let dislikes: Vec<f64> = runs
+                .iter()
+                .filter(|r| r.valid)
+                .filter_map(|r| r.dislike)
+                .collect();
+            let mean_dislike = if dislikes.is_empty() {
+                None
+            } else {
+                Some(dislikes.iter().sum::<f64>() / dislikes.len() as f64)
+            };
+            let score = GroupScore {
+                runs: runs.len(),
+                valid_runs: dislikes.len(),
+                mean_dislike,
+                mean_elapsed: runs.iter().map(|r| r.elapsed).sum::<f64>() / runs.len() as f64,
+            };
+            (key, score)
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Regression,
+    Improvement,
+    Unchanged,
+    // ベースラインに同じ問題・反復回数の実行がない
+    New,
+}
+
+#[derive(Debug, Clone)]
+pub struct Comparison {
+    pub problem: u32,
+    pub iterations: u64,
+    pub baseline: Option<GroupScore>,
+    pub current: GroupScore,
+    pub verdict: Verdict,
+}
+
+// 妥当な解が得られた実行が減ったか、dislike の平均が threshold の割合以上 (かつ 1 以上) 悪くなったら退行とみなす
+pub fn compare(
+    baseline: &BenchmarkResult,
+    current: &BenchmarkResult,
+    threshold: f64,
+) -> Vec<Comparison> {
+    let baseline_groups = summarize(&baseline.runs);
+    summarize(&current.runs)
+        .into_iter()
+        .map(|((problem, iterations), current)| {
+            let baseline = baseline_groups.get(&(problem, iterations)).copied();
+            let verdict = match baseline {
+                None => Verdict::New,
+                Some(b) if current.valid_runs < b.valid_runs => Verdict::Regression,
+                Some(b) if current.valid_runs > b.valid_runs => Verdict::Improvement,
+                Some(b) => match (b.mean_dislike, current.mean_dislike) {
+                    (Some(bd), Some(cd)) if cd > bd * (1.0 + threshold) && cd - bd >= 1.0 => {
+                        Verdict::Regression
+                    }
+                    (Some(bd), Some(cd)) if cd < bd * (1.0 - threshold) && bd - cd >= 1.0 => {
+                        Verdict::Improvement
+                    }
+                    _ => Verdict::Unchanged,
+                },
+            };
+            Comparison {
+                problem,
+                iterations,
+                baseline,
+                current,
+                verdict,
+            }
+        })
+        .collect()
+}
+
+fn format_score(score: &Option<GroupScore>) -> String {
+    match score {
+        Some(GroupScore {
+            runs,
+            valid_runs,
+            mean_dislike: Some(d),
+            ..
+        }) => format!("{:.1} ({}/{})", d, valid_runs, runs),
+        Some(GroupScore {
+            runs, valid_runs, ..
+        }) => format!("- ({}/{})", valid_runs, runs),
+        None => "-".to_string(),
+    }
+}
+
+pub fn print_comparisons(comparisons: &[Comparison]) {
+    eprintln!(
+        "{:>7} {:>10} {:>20} {:>20} {:>8}  verdict",
+        "problem", "iterations", "baseline", "current", "time"
+    );
+    for c in comparisons.iter() {
+        let verdict = match c.verdict {
+            Verdict::Regression => "REGRESSION",
+            Verdict::Improvement => "improved",
+            Verdict::Unchanged => "",
+            Verdict::New => "new",
+        };
+        eprintln!(
+            "{:>7} {:>10} {:>20} {:>20} {:>7.2}s  {}",
+            c.problem,
+            c.iterations,
+            format_score(&c.baseline),
+            format_score(&Some(c.current)),
+            c.current.mean_elapsed,
+            verdict
+        );
+    }
+}
+
+#[test]
+fn test_parse_ids() {
+    assert_eq!(parse_ids("1-3,10"), vec![1, 2, 3, 10]);
+    assert_eq!(parse_ids("7"), vec![7]);
+}
+
+#[test]
+fn test_compare() {
+    let run = |problem: u32, seed: u64, dislike: f64, valid: bool| BenchmarkRun {
+        problem,
+        seed,
+        iterations: 1000,
+        dislike: Some(dislike),
+        valid,
+        elapsed: 1.0,
+    };
+    let baseline = BenchmarkResult {
+        pipeline: "default".to_string(),
+        runs: vec![
+            run(1, 0, 100.0, true),
+            run(1, 1, 200.0, true),
+            run(2, 0, 100.0, true),
+            run(3, 0, 100.0, true),
+        ],
+    };
+    let current = BenchmarkResult {
+        pipeline: "default".to_string(),
+        runs: vec![
+            run(1, 0, 100.0, true),
+            run(1, 1, 210.0, true),
+            run(2, 0, 120.0, true),
+            run(3, 0, 50.0, false),
+            run(4, 0, 0.0, true),
+        ],
+    };
+    let verdicts: Vec<Verdict> = compare(&baseline, &current, 0.05)
+        .iter()
+        .map(|c| c.verdict)
+        .collect();
+    assert_eq!(
+        verdicts,
+        vec![
+            Verdict::Unchanged,
+            Verdict::Regression,
+            Verdict::Regression,
+            Verdict::New
+        ]
+    );
+}
@@ -0,0 +1,214 @@
+use crate::stats::StopReason;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+// wasm32 では std::time::Instant が使えないので、ブラウザでは performance.now() を使うものにする
+use web_time::Instant;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+// SIGINT/SIGTERM を受け取ったら全ての予算を使い切ったことにして、各ステージにその時点の最良解を返させる
+#[cfg(not(target_arch = "wasm32"))]
+pub fn install_interrupt_handler() {
+    ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            // 2 回目は後始末を待たずに終了する
+            std::process::exit(130);
+        }
+        eprintln!("interrupted");
+    })
+    .expect("failed to set signal handler");
+}
+
+pub fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Limit {
+    Time(Duration),
+    // 各ソルバーの数える反復回数 (StageStats::iterations と同じもの) での上限。
+    // 経過時間に依存しないので、FIX_SEED と組み合わせるとどのマシンでも同じ結果になる
+    Iterations(u64),
+}
+
+// ソルバーが使ってよい実行時間、あるいは反復回数
+#[derive(Debug, Clone)]
+pub struct Budget {
+    start_at: Instant,
+    limit: Limit,
+}
+
+impl Budget {
+    pub fn new(time_limit: Duration) -> Budget {
+        Budget {
+            start_at: Instant::now(),
+            limit: Limit::Time(time_limit),
+        }
+    }
+
+    pub fn with_iterations(iteration_limit: u64) -> Budget {
+        Budget {
+            start_at: Instant::now(),
+            limit: Limit::Iterations(iteration_limit),
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Instant::now() - self.start_at
+    }
+
+    // iter はそのソルバーがこれまでに数えた反復回数
+    pub fn is_over(&self, iter: u64) -> bool {
+        if is_interrupted() {
+            return true;
+        }
+        match self.limit {
+            Limit::Time(time_limit) => self.elapsed() >= time_limit,
+            Limit::Iterations(iteration_limit) => iter >= iteration_limit,
+        }
+    }
+
+    // is_over() で止まったときの理由
+    pub fn stop_reason(&self) -> StopReason {
+        if is_interrupted() {
+            return StopReason::Interrupted;
+        }
+        match self.limit {
+            Limit::Time(_) => StopReason::TimeLimit,
+            Limit::Iterations(_) => StopReason::IterationLimit,
+        }
+    }
+
+    // 0.0 から 1.0 までの進み具合。温度の計算などに使う
+    pub fn progress(&self, iter: u64) -> f64 {
+        let (done, total) = match self.limit {
+            Limit::Time(time_limit) => (self.elapsed().as_secs_f64(), time_limit.as_secs_f64()),
+            Limit::Iterations(iteration_limit) => (iter as f64, iteration_limit as f64),
+        };
+        if total == 0.0 {
+            return 1.0;
+        }
+        (done / total).min(1.0)
+    }
+
+    // 残りの予算のうち fraction の割合を持つ新しい予算。
+    // 反復回数の予算は実際に使われた回数がわからないので、allocated (これまでに配った回数) を引いた残りから配る
+    fn split(&self, fraction: f64, allocated: u64) -> Budget {
+        match self.limit {
+            Limit::Time(time_limit) => {
+                Budget::new(time_limit.saturating_sub(self.elapsed()).mul_f64(fraction))
+            }
+            Limit::Iterations(iteration_limit) => Budget::with_iterations(
+                (iteration_limit.saturating_sub(allocated) as f64 * fraction).round() as u64,
+            ),
+        }
+    }
+
+    fn iteration_limit(&self) -> u64 {
+        match self.limit {
+            Limit::Time(_) => 0,
+            Limit::Iterations(iteration_limit) => iteration_limit,
+        }
+    }
+}
+
+// パイプライン全体の予算を各ステージに配分する。
+// ステージの予算は「残り時間 × (そのステージの重み / まだ実行していないステージの重みの和)」なので、
+// 前のステージが使い切らなかった時間や、スキップされたステージの分は後ろのステージに回る。
+// 反復回数の予算ではスキップされたステージの分だけが後ろに回る
+#[derive(Debug, Clone)]
+pub struct BudgetPlan {
+    total: Budget,
+    weights: Vec<(String, f64)>,
+    next: usize,
+    allocated: u64,
+}
+
+pub const DEFAULT_STAGE_WEIGHTS: [(&str, f64); 5] = [
+    ("initial", 0.3),
+    ("orthgonal1", 0.05),
+    ("annealing", 0.55),
+    ("orthgonal2", 0.05),
+    ("adjust", 0.05),
+];
+
+impl BudgetPlan {
+    pub fn new(total: Budget, weights: Vec<(String, f64)>) -> BudgetPlan {
+        BudgetPlan {
+            total,
+            weights,
+            next: 0,
+            allocated: 0,
+        }
+    }
+
+    // "initial=0.2,annealing=0.7" のような指定で DEFAULT_STAGE_WEIGHTS の一部を上書きする
+    pub fn parse_weights(s: &str) -> Vec<(String, f64)> {
+        let mut weights: Vec<(String, f64)> = DEFAULT_STAGE_WEIGHTS
+            .iter()
+            .map(|(name, w)| (name.to_string(), *w))
+            .collect();
+        for item in s.split(',').filter(|item| !item.is_empty()) {
+            let mut kv = item.splitn(2, '=');
+            let name = kv.next().unwrap().trim();
+            let w: f64 = kv
+                .next()
+                .and_then(|v| v.trim().parse().ok())
+                .unwrap_or_else(|| panic!("Invalid stage fraction {}", item));
+            match weights.iter_mut().find(|(n, _)| n == name) {
+                Some(entry) => entry.1 = w,
+                None => panic!("Unknown stage {}", name),
+            }
+        }
+        weights
+    }
+
+    // name のステージの予算を返す。name より前のステージはもう実行しないものとして扱う
+    pub fn stage(&mut self, name: &str) -> Budget {
+        let i = self.weights[self.next..]
+            .iter()
+            .position(|(n, _)| n == name)
+            .map(|i| i + self.next)
+            .unwrap_or_else(|| panic!("stage {} is not planned", name));
+        let rest: f64 = self.weights[i..].iter().map(|(_, w)| w).sum();
+        let fraction = if rest > 0.0 {
+            self.weights[i].1 / rest
+        } else {
+            0.0
+        };
+        self.next = i + 1;
+        let budget = self.total.split(fraction, self.allocated);
+        self.allocated += budget.iteration_limit();
+        budget
+    }
+}
+
+#[test]
+fn test_budget_plan() {
+    let weights = BudgetPlan::parse_weights("initial=0.5,annealing=0.5");
+    assert_eq!(weights[0], ("initial".to_string(), 0.5));
+    let time_limit = |budget: &Budget| match budget.limit {
+        Limit::Time(time_limit) => time_limit.as_secs_f64(),
+        Limit::Iterations(_) => panic!("not a time budget"),
+    };
+    let mut plan = BudgetPlan::new(Budget::new(Duration::from_secs(100)), weights);
+    let initial = plan.stage("initial");
+    assert!((time_limit(&initial) - 100.0 * 0.5 / 1.15).abs() < 0.1);
+    // orthgonal1 をスキップすると、その分は後ろのステージに回る
+    let annealing = plan.stage("annealing");
+    assert!((time_limit(&annealing) - 100.0 * 0.5 / 0.6).abs() < 0.1);
+}
+
+#[test]
+fn test_budget_plan_iterations() {
+    let weights = BudgetPlan::parse_weights("initial=0.5,annealing=0.5");
+    let mut plan = BudgetPlan::new(Budget::with_iterations(1150), weights);
+    let initial = plan.stage("initial");
+    assert_eq!(initial.iteration_limit(), 500);
+    assert!(!initial.is_over(499));
+    assert!(initial.is_over(500));
+    assert_eq!(initial.progress(250), 0.5);
+    assert_eq!(initial.stop_reason(), StopReason::IterationLimit);
+    let annealing = plan.stage("annealing");
+    assert_eq!(annealing.iteration_limit(), 542);
+}
@@ -2,8 +2,25 @@ pub type Point = geo::Point<f64>;
 pub type Polygon = geo::Polygon<f64>;
 pub type Line = geo::Line<f64>;
 use geo::algorithm::contains::Contains;
-use std::collections::VecDeque;
+use rand::prelude::*;
+use std::collections::{HashMap, VecDeque};
+
+// seed が指定されていれば base を seed で変えた固定の系列 (seed = 0 なら base そのもの)、
+// 指定されていなければ実行ごとに異なる系列の乱数生成器を返す
+pub fn make_rng(base: &[u8; 32], seed: Option<u64>) -> SmallRng {
+    match seed {
+        Some(seed) => {
+            let mut s = *base;
+            for (b, x) in s.iter_mut().zip(seed.to_le_bytes().iter()) {
+                *b ^= x;
+            }
+            SmallRng::from_seed(s)
+        }
+        None => SmallRng::from_entropy(),
+    }
+}
 
+/// 図形の辺。v, w は頂点の番号
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub struct Edge {
     pub v: usize,
@@ -40,6 +57,7 @@ impl BonusType {
     }
 }
 
+/// 図形。vertices は元の位置で、辺の長さの基準になる
 #[derive(Debug, Clone)]
 pub struct Figure {
     pub edges: Vec<Edge>,
@@ -53,6 +71,7 @@ pub struct Bonus {
     pub problem: i64,
 }
 
+/// 問題 (穴、図形、epsilon、ボーナス)
 #[derive(Debug, Clone)]
 pub struct Input {
     pub hole: Polygon,
@@ -71,6 +90,7 @@ pub fn distance(a: &Point, b: &Point) -> f64 {
     squared_distance(a, b).sqrt()
 }
 
+/// 穴の各頂点から最も近いポーズの頂点までの距離の 2 乗の和
 pub fn calculate_dislike(vertices: &[Point], hole: &Polygon) -> f64 {
     let mut s = 0.0;
     for h in hole.exterior().points_iter().skip(1) {
@@ -111,6 +131,7 @@ pub fn does_point_fit_in_hole(p: &Point, hole: &Polygon) -> bool {
     return true;
 }
 
+/// 線分 p1-p2 が穴の中 (境界を含む) に収まっているか
 pub fn does_line_fit_in_hole(p1: &Point, p2: &Point, hole: &Polygon) -> bool {
     let line = Line::new(*p1, *p2);
     if !hole.contains(&line) {
@@ -169,6 +190,8 @@ pub fn does_figure_fit_in_hole(figure: &Figure, hole: &Polygon, wall_hack: bool)
     true
 }
 
+/// ポーズが問題の規則を全て満たしているか。辺の長さと穴に収まっていることを、
+/// used_bonus_types のボーナス (BREAK_A_LEG なら折る辺 break_leg) を使う前提で調べる
 pub fn does_valid_pose(
     vertices: &Vec<Point>,
     figure: &Figure,
@@ -254,6 +277,70 @@ pub fn test_does_valid_pose() {
     ));
 }
 
+// ポーズがどの制約を満たしていないかの詳細 (ボーナスは考慮しない)
+#[derive(Debug, Clone, PartialEq)]
+pub struct PoseReport {
+    pub dislike: f64,
+    // 長さの制約を満たさない辺 (辺の番号, calc_distance_ratio の値)
+    pub stretched_edges: Vec<(usize, f64)>,
+    // 穴からはみ出している辺の番号
+    pub outside_edges: Vec<usize>,
+}
+
+impl PoseReport {
+    pub fn is_valid(&self) -> bool {
+        self.stretched_edges.is_empty() && self.outside_edges.is_empty()
+    }
+}
+
+pub fn make_pose_report(vertices: &[Point], input: &Input) -> PoseReport {
+    let mut stretched_edges = vec![];
+    let mut outside_edges = vec![];
+    for (i, e) in input.figure.edges.iter().enumerate() {
+        let p1 = vertices[e.v];
+        let p2 = vertices[e.w];
+        let original_p1 = input.figure.vertices[e.v];
+        let original_p2 = input.figure.vertices[e.w];
+        if !is_allowed_distance(&p1, &p2, &original_p1, &original_p2, input.epsilon, false) {
+            let ratio = calc_distance_ratio(&p1, &p2, &original_p1, &original_p2);
+            stretched_edges.push((i, ratio));
+        }
+        if !does_line_fit_in_hole(&p1, &p2, &input.hole) {
+            outside_edges.push(i);
+        }
+    }
+    PoseReport {
+        dislike: calculate_dislike(vertices, &input.hole),
+        stretched_edges,
+        outside_edges,
+    }
+}
+
+#[test]
+fn test_make_pose_report() {
+    let input = crate::inout::parse_input(
+        r#"{"hole":[[0,0],[10,0],[10,10],[0,10]],"epsilon":0,"figure":{"edges":[[0,1],[1,2]],"vertices":[[0,0],[4,0],[4,4]]},"bonuses":[]}"#,
+    );
+    let ps = vec![
+        Point::new(0.0, 0.0),
+        Point::new(4.0, 0.0),
+        Point::new(4.0, 4.0),
+    ];
+    let report = make_pose_report(&ps, &input);
+    assert!(report.is_valid());
+    assert_eq!(report.dislike, calculate_dislike(&ps, &input.hole));
+
+    let ps = vec![
+        Point::new(0.0, 0.0),
+        Point::new(5.0, 0.0),
+        Point::new(5.0, 14.0),
+    ];
+    let report = make_pose_report(&ps, &input);
+    assert!(!report.is_valid());
+    assert_eq!(report.stretched_edges.len(), 2);
+    assert_eq!(report.outside_edges, vec![1]);
+}
+
 // #[test]
 // fn test_contains() {
 //     let l1 = Line::new(Point::new(0.0, 10.0), Point::new(20.0, 10.0));
@@ -346,6 +433,7 @@ impl Ring {
     }
 }
 
+/// p1-p2 の長さが元の辺 original_p1-original_p2 の長さから epsilon の範囲に収まっているか
 pub fn is_allowed_distance(
     p1: &Point,
     p2: &Point,
@@ -543,6 +631,188 @@ fn test_ring_points() {
     assert!(ring_points(&ring).contains(&p1));
 }
 
+/// 穴の中 (境界を含む) の格子点
+pub fn lattice_points_in_hole(hole: &Polygon) -> Vec<Point> {
+    let hole_points: Vec<Point> = hole.exterior().points_iter().collect();
+    let (min, max) = calc_bound_box(&hole_points);
+    let mut ps = vec![];
+    for y in (min.y().ceil() as i64)..=(max.y().floor() as i64) {
+        for x in (min.x().ceil() as i64)..=(max.x().floor() as i64) {
+            let p = Point::new(x as f64, y as f64);
+            if does_point_fit_in_hole(&p, hole) {
+                ps.push(p);
+            }
+        }
+    }
+    ps
+}
+
+/// 元の長さの 2 乗ごとに、各格子点 candidates[k] からその長さの辺で届き、
+/// 辺が穴に収まる格子点の番号の列を求める。長さが同じ辺は同じものを使える
+pub fn make_reachable_lattice_points(
+    input: &Input,
+    candidates: &[Point],
+) -> HashMap<i64, Vec<Vec<usize>>> {
+    let index: HashMap<(i64, i64), usize> = candidates
+        .iter()
+        .enumerate()
+        .map(|(k, p)| ((p.x() as i64, p.y() as i64), k))
+        .collect();
+    let mut reachable = HashMap::new();
+    for edge in input.figure.edges.iter() {
+        let original_squared_distance = squared_distance(
+            &input.figure.vertices[edge.v],
+            &input.figure.vertices[edge.w],
+        );
+        reachable
+            .entry(original_squared_distance as i64)
+            .or_insert_with(|| {
+                candidates
+                    .iter()
+                    .map(|c| {
+                        let ring = Ring::from_epsilon(*c, input.epsilon, original_squared_distance);
+                        let mut qs = vec![];
+                        each_ring_points(&ring, |q| {
+                            if let Some(&k) = index.get(&(q.x() as i64, q.y() as i64)) {
+                                if does_line_fit_in_hole(c, &q, &input.hole) {
+                                    qs.push(k);
+                                }
+                            }
+                        });
+                        qs
+                    })
+                    .collect()
+            });
+    }
+    reachable
+}
+
+/// 辺 e の元の長さの 2 乗。make_reachable_lattice_points の鍵になる
+pub fn original_squared_length(input: &Input, e: &Edge) -> i64 {
+    squared_distance(&input.figure.vertices[e.v], &input.figure.vertices[e.w]) as i64
+}
+
+/// 元の長さの 2 乗ごとに、is_allowed_distance を満たす整数の差分 (dx, dy) を全て持つ表。
+/// 頂点を置ける格子点を、隣の頂点の表の共通部分として浮動小数点の誤差なしに列挙できる
+#[derive(Debug, Clone)]
+pub struct RingOffsetTable {
+    offsets: HashMap<i64, Vec<(i64, i64)>>,
+    // 長さごとに、[-r, r]^2 の差分が表にあるかを並べたもの。HashSet より速く引ける
+    members: HashMap<i64, (i64, Vec<bool>)>,
+}
+
+impl RingOffsetTable {
+    pub fn new(input: &Input) -> RingOffsetTable {
+        let origin = Point::new(0.0, 0.0);
+        let mut offsets = HashMap::new();
+        for e in input.figure.edges.iter() {
+            let original_p1 = input.figure.vertices[e.v];
+            let original_p2 = input.figure.vertices[e.w];
+            offsets
+                .entry(original_squared_length(input, e))
+                .or_insert_with(|| {
+                    // ring は誤差のぶん少し広いので、is_allowed_distance で絞る
+                    let ring = Ring::from_epsilon(
+                        origin,
+                        input.epsilon,
+                        squared_distance(&original_p1, &original_p2),
+                    );
+                    let mut ds = vec![];
+                    each_ring_points(&ring, |d| {
+                        if is_allowed_distance(
+                            &origin,
+                            &d,
+                            &original_p1,
+                            &original_p2,
+                            input.epsilon,
+                            false,
+                        ) {
+                            ds.push((d.x() as i64, d.y() as i64));
+                        }
+                    });
+                    ds
+                });
+        }
+        let members = offsets
+            .iter()
+            .map(|(&l, ds)| {
+                let r = ds.iter().map(|&(dx, dy)| dx.abs().max(dy.abs())).max().unwrap_or(0);
+                let mut grid = vec![false; ((2 * r + 1) * (2 * r + 1)) as usize];
+                for &(dx, dy) in ds.iter() {
+                    grid[((dy + r) * (2 * r + 1) + dx + r) as usize] = true;
+                }
+                (l, (r, grid))
+            })
+            .collect();
+        RingOffsetTable { offsets, members }
+    }
+
+    pub fn offsets(&self, original_squared_length: i64) -> &[(i64, i64)] {
+        &self.offsets[&original_squared_length]
+    }
+
+    pub fn contains(&self, original_squared_length: i64, dx: i64, dy: i64) -> bool {
+        let (r, grid) = &self.members[&original_squared_length];
+        dx.abs() <= *r && dy.abs() <= *r && grid[((dy + r) * (2 * r + 1) + dx + r) as usize]
+    }
+
+    /// 頂点 i を置いたときに、隣の頂点全てとの辺の長さが許される格子点。
+    /// 表が最も小さい隣の頂点から列挙して、残りの隣の頂点の表で絞る。線分が穴に収まるかは見ない
+    pub fn common_points(
+        &self,
+        i: usize,
+        solution: &[Point],
+        original_vertices: &[Point],
+        out_edges: &[Vec<usize>],
+    ) -> Vec<Point> {
+        let length = |w: usize| squared_distance(&original_vertices[i], &original_vertices[w]) as i64;
+        let base = match out_edges[i]
+            .iter()
+            .copied()
+            .min_by_key(|&w| self.offsets(length(w)).len())
+        {
+            Some(w) => w,
+            None => return vec![],
+        };
+        let (bx, by) = (solution[base].x() as i64, solution[base].y() as i64);
+        self.offsets(length(base))
+            .iter()
+            .map(|&(dx, dy)| (bx + dx, by + dy))
+            .filter(|&(x, y)| {
+                out_edges[i].iter().all(|&w| {
+                    w == base
+                        || self.contains(
+                            length(w),
+                            x - solution[w].x() as i64,
+                            y - solution[w].y() as i64,
+                        )
+                })
+            })
+            .map(|(x, y)| Point::new(x as f64, y as f64))
+            .collect()
+    }
+}
+
+#[test]
+fn test_ring_offset_table() {
+    let input = crate::inout::parse_input(
+        r#"{"hole":[[0,0],[10,0],[10,10],[0,10]],"epsilon":0,"figure":{"edges":[[0,1],[1,2]],"vertices":[[0,0],[5,0],[5,5]]},"bonuses":[]}"#,
+    );
+    let table = RingOffsetTable::new(&input);
+    // 長さ 5 の差分は (±5,0), (0,±5), (±3,±4), (±4,±3) の 12 個
+    assert_eq!(table.offsets(25).len(), 12);
+    assert!(table.contains(25, -3, 4));
+    assert!(!table.contains(25, 3, 3));
+
+    // 頂点 1 は (0,0) と (5,5) のどちらからも 5 離れた (5,0) か (0,5)
+    let mut solution = input.figure.vertices.clone();
+    solution[1] = Point::new(-100.0, -100.0);
+    let out_edges = make_out_edges(&input.figure.edges, 3);
+    let mut points = table.common_points(1, &solution, &input.figure.vertices, &out_edges);
+    points.sort_by(|a, b| a.x().partial_cmp(&b.x()).unwrap());
+    assert_eq!(points, vec![Point::new(0.0, 5.0), Point::new(5.0, 0.0)]);
+}
+
 pub fn make_out_edges(edges: &[Edge], n_vertices: usize) -> Vec<Vec<usize>> {
     let mut out_edges = vec![vec![]; n_vertices];
     for e in edges.iter() {
@@ -552,6 +822,117 @@ pub fn make_out_edges(edges: &[Edge], n_vertices: usize) -> Vec<Vec<usize>> {
     out_edges
 }
 
+/// 頂点 u, v の間の距離の上限 (最短経路に沿って辺の ring の外径を足したもの)。
+/// 連結でない頂点の組は f64::INFINITY
+pub fn make_stretch_bounds(input: &Input) -> Vec<Vec<f64>> {
+    let n = input.figure.vertices.len();
+    let mut bounds = vec![vec![f64::INFINITY; n]; n];
+    for (v, row) in bounds.iter_mut().enumerate() {
+        row[v] = 0.0;
+    }
+    for e in input.figure.edges.iter() {
+        let ring = Ring::from_epsilon(
+            Point::new(0.0, 0.0),
+            input.epsilon,
+            squared_distance(&input.figure.vertices[e.v], &input.figure.vertices[e.w]),
+        );
+        bounds[e.v][e.w] = bounds[e.v][e.w].min(ring.outer_radius);
+        bounds[e.w][e.v] = bounds[e.v][e.w];
+    }
+    // Warshall-Floyd
+    for k in 0..n {
+        for i in 0..n {
+            if bounds[i][k] == f64::INFINITY {
+                continue;
+            }
+            for j in 0..n {
+                let d = bounds[i][k] + bounds[k][j];
+                if d < bounds[i][j] {
+                    bounds[i][j] = d;
+                }
+            }
+        }
+    }
+    bounds
+}
+
+/// p に置いた頂点 v が、置き済みの (v 以外の) 全ての頂点から距離の上限の中にあるか
+pub fn is_within_stretch_bounds(
+    stretch_bounds: &[Vec<f64>],
+    v: usize,
+    p: &Point,
+    vertices: &[Point],
+    placed: &[bool],
+) -> bool {
+    (0..vertices.len()).all(|u| {
+        u == v || !placed[u] || squared_distance(p, &vertices[u]) <= pow2(stretch_bounds[u][v])
+    })
+}
+
+/// 置き済みの頂点から求めた dislike の下界。
+/// まだ置いていない頂点 v と穴の頂点 h の距離は、置き済みの u について
+/// |h - p_u| - stretch_bounds[u][v] 以上になる
+pub fn calc_dislike_lower_bound(
+    vertices: &[Point],
+    placed: &[bool],
+    stretch_bounds: &[Vec<f64>],
+    hole: &Polygon,
+) -> f64 {
+    let placed_vertices: Vec<usize> = (0..vertices.len()).filter(|&u| placed[u]).collect();
+    if placed_vertices.is_empty() {
+        return 0.0;
+    }
+    let mut s = 0.0;
+    for h in hole.exterior().points_iter().skip(1) {
+        let hole_distances: Vec<f64> = placed_vertices
+            .iter()
+            .map(|&u| distance(&h, &vertices[u]))
+            .collect();
+        let mut nearest = f64::INFINITY;
+        for v in 0..vertices.len() {
+            let d = if placed[v] {
+                distance(&h, &vertices[v])
+            } else {
+                placed_vertices
+                    .iter()
+                    .zip(hole_distances.iter())
+                    .map(|(&u, &d)| d - stretch_bounds[u][v])
+                    .fold(0.0, f64::max)
+            };
+            nearest = nearest.min(d);
+            if nearest == 0.0 {
+                break;
+            }
+        }
+        s += pow2(nearest);
+    }
+    s
+}
+
+#[test]
+fn test_stretch_bounds() {
+    let input = crate::inout::parse_input(
+        r#"{"hole":[[0,0],[10,0],[10,10],[0,10]],"epsilon":0,"figure":{"edges":[[0,1],[1,2]],"vertices":[[0,0],[3,0],[3,4]]},"bonuses":[]}"#,
+    );
+    let bounds = make_stretch_bounds(&input);
+    assert!((bounds[0][2] - 7.0).abs() < 1e-6);
+    assert!((bounds[2][0] - 7.0).abs() < 1e-6);
+    assert_eq!(bounds[1][1], 0.0);
+
+    let mut vertices = input.figure.vertices.clone();
+    let placed = vec![true, false, false];
+    vertices[0] = Point::new(0.0, 0.0);
+    assert!(is_within_stretch_bounds(&bounds, 2, &Point::new(7.0, 0.0), &vertices, &placed));
+    assert!(!is_within_stretch_bounds(&bounds, 2, &Point::new(7.0, 1.0), &vertices, &placed));
+
+    // (0,0) に置いた頂点 0 から頂点 2 は 7 までしか離れられないので、
+    // (10,0), (0,10) までは 3 以上、(10,10) までは 10√2 - 7 以上離れる
+    let lower_bound = calc_dislike_lower_bound(&vertices, &placed, &bounds, &input.hole);
+    let expected = pow2(10.0 - 7.0) + pow2(200f64.sqrt() - 7.0) + pow2(10.0 - 7.0);
+    assert!((lower_bound - expected).abs() < 1e-6);
+    assert!(lower_bound <= calculate_dislike(&input.figure.vertices, &input.hole));
+}
+
 pub fn make_determined_order(out_edges: &Vec<Vec<usize>>, start: Option<usize>) -> Vec<usize> {
     let n = out_edges.len();
     let mut order = vec![0; n];
@@ -585,144 +966,408 @@ pub fn make_determined_order(out_edges: &Vec<Vec<usize>>, start: Option<usize>)
     order
 }
 
+// fix_allowed_distance_violation の結果
+#[derive(Debug, Clone, PartialEq)]
+pub struct Repair {
+    pub solution: Vec<Point>,
+    // 長さの制約を満たさないか穴からはみ出している辺の番号。呼ぶ前から違反していた辺も含む
+    pub violated_edges: Vec<usize>,
+}
+
+impl Repair {
+    pub fn is_valid(&self) -> bool {
+        self.violated_edges.is_empty()
+    }
+
+    // 全ての辺を直せたときだけ直したポーズを返す
+    pub fn into_valid(self) -> Option<Vec<Point>> {
+        if self.is_valid() {
+            Some(self.solution)
+        } else {
+            None
+        }
+    }
+}
+
+// 全ての辺について射影する回数の上限
+const REPAIR_MAX_ITERATIONS: usize = 30;
+// 丸めるときに射影した位置の周りで探す範囲 (チェビシェフ距離)
+const REPAIR_ROUND_RADIUS: i64 = 3;
+// 丸めるときに格子点が見つからない頂点がこれより多ければ、探すのをやめる
+const REPAIR_MAX_UNPLACED: usize = 2;
+// 丸めた後に違反の残る頂点を動かして探す範囲 (チェビシェフ距離)
+const REPAIR_SEARCH_RADIUS: i64 = 2;
+
+// start_point_index の頂点を動かした後、ずれた辺を直す。
+// 全ての辺について、長さが許される範囲から外れていれば両端を半分ずつ動かして範囲に入れ (始点は動かさない)、
+// 穴の外に出た頂点を境界に戻す、という射影を、全ての辺の長さが範囲に入るか REPAIR_MAX_ITERATIONS 回まで繰り返す。
+// その後で determined_orders[start_point_index] の順に、丸め終えた隣の頂点との辺を満たす格子点に丸め、
+// 違反の残る頂点を同じ順に近くの格子点へ動かすのを、どの頂点も動かなくなるまで繰り返す。
+// search_limit を渡すと、頂点を動かして探すのはその回数まで。直せなかった辺は、呼ぶ前から違反していた辺も含めて返す
 pub fn fix_allowed_distance_violation(
     start_point_index: usize,
     solution: &Vec<Point>,
     input: &Input,
     out_edges: &Vec<Vec<usize>>,
     determined_orders: &Vec<Vec<usize>>,
-) -> Option<Vec<Point>> {
-    let mut solution = solution.clone();
+    search_limit: Option<usize>,
+) -> Repair {
     let n = input.figure.vertices.len();
-    let order = &determined_orders[start_point_index];
-    let mut determined = vec![false; n];
-    let mut moved = vec![false; n];
-    determined[start_point_index] = true;
-    moved[start_point_index] = true;
-    for index in 1..n {
-        let from = order[index];
-        determined[from] = true;
-        let mut p = solution[from];
-        for _iteration in 0..3 {
-            for &to in out_edges[from].iter() {
-                if !determined[to] {
-                    continue;
-                }
-                let d1 = calc_distance_ratio(
-                    &solution[to],
-                    &p,
-                    &input.figure.vertices[to],
-                    &input.figure.vertices[from],
-                );
-                if d1.abs() + 1e-6 <= input.epsilon as f64 / 1000000.0 {
-                    continue;
+    let start = start_point_index;
+    let edges = &input.figure.edges;
+    let epsilon = input.epsilon as f64 / 1000000.0;
+
+    // 長さを満たさなければ、許される範囲からの超過を返す
+    let length_violation = |result: &[Point], v: usize, w: usize| -> Option<f64> {
+        let original_v = &input.figure.vertices[v];
+        let original_w = &input.figure.vertices[w];
+        if is_allowed_distance(
+            &result[v],
+            &result[w],
+            original_v,
+            original_w,
+            input.epsilon,
+            false,
+        ) {
+            return None;
+        }
+        let ratio = calc_distance_ratio(&result[v], &result[w], original_v, original_w);
+        Some((ratio.abs() - epsilon).max(0.0))
+    };
+    // 長さを満たさないか穴からはみ出していれば、長さの超過 (穴だけなら 1.0) を返す。
+    // 穴の判定は重いので、長さが合っているときだけ見る
+    let edge_violation = |result: &[Point], v: usize, w: usize| -> Option<f64> {
+        length_violation(result, v, w).or_else(|| {
+            if does_line_fit_in_hole(&result[v], &result[w], &input.hole) {
+                None
+            } else {
+                Some(1.0)
+            }
+        })
+    };
+    let violated_edges = |result: &[Point]| -> Vec<usize> {
+        edges
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| edge_violation(result, e.v, e.w).is_some())
+            .map(|(k, _)| k)
+            .collect()
+    };
+
+    // ずれが小さければ、射影しなくても今の位置の近くに丸めるだけで直る
+    let fixed: Vec<bool> = (0..n).map(|i| i == start).collect();
+    let (result, n_unplaced) = round_to_lattice(
+        start,
+        solution,
+        solution,
+        fixed,
+        input,
+        out_edges,
+        &determined_orders[start],
+        0,
+    );
+    if n_unplaced == 0 {
+        let violated_edges = violated_edges(&result);
+        if violated_edges.is_empty() {
+            return Repair {
+                solution: result,
+                violated_edges,
+            };
+        }
+    }
+
+    let mut ps = solution.clone();
+    for _iteration in 0..REPAIR_MAX_ITERATIONS {
+        let mut touched = vec![false; n];
+        let mut touched_vertices = vec![];
+        for e in edges.iter() {
+            let (v, w) = (e.v, e.w);
+            let d = ps[w] - ps[v];
+            let length = d.dot(d).sqrt();
+            // 許される長さの範囲。丸めの誤差で外れないように、射影するときは範囲の真ん中の半分に入れる
+            let sd = squared_distance(&input.figure.vertices[v], &input.figure.vertices[w]);
+            let lo = (sd * (1.0 - epsilon)).max(0.0).sqrt();
+            let hi = (sd * (1.0 + epsilon)).sqrt();
+            if lo - 1e-9 <= length && length <= hi + 1e-9 {
+                continue;
+            }
+            // 両端が重なっていると向きが決まらないので、元の図形の辺の向きに引き離す
+            let direction = if length < 1e-9 {
+                let original = input.figure.vertices[w] - input.figure.vertices[v];
+                original / original.dot(original).sqrt()
+            } else {
+                d / length
+            };
+            let margin = (hi - lo) / 4.0;
+            let target = length.clamp(lo + margin, hi - margin);
+            let correction = direction * (length - target);
+            let (wv, ww) = if v == start {
+                (0.0, 1.0)
+            } else if w == start {
+                (1.0, 0.0)
+            } else {
+                (0.5, 0.5)
+            };
+            ps[v] = ps[v] + correction * wv;
+            ps[w] = ps[w] - correction * ww;
+            for u in [v, w] {
+                if u != start && !touched[u] {
+                    touched[u] = true;
+                    touched_vertices.push(u);
                 }
-                // 距離が条件を満たしてない場合は満たすように移動させる
-                let distance_ratio = squared_distance(&solution[to], &p).sqrt()
-                    / squared_distance(&input.figure.vertices[to], &input.figure.vertices[from])
-                        .sqrt()
-                    - 1.0;
-                let vect = (solution[to] - solution[from])
-                    * distance_ratio
-                    * (input.epsilon as f64 / 1000000.0).sqrt();
-                // eprintln!(
-                //     "{} {} {} {:?}",
-                //     squared_distance(&solution[to], &p).sqrt(),
-                //     squared_distance(&input.figure.vertices[to], &input.figure.vertices[from])
-                //         .sqrt(),
-                //     distance_ratio,
-                //     vect
-                // );
-                p = p + vect;
-            }
-        }
-        let mut ok = false;
-        // マンハッタン距離r以内の全点を試す
-        'outer_loop: for r in 0i64..5i64 {
-            for dx in -r..=r {
-                let mut dys = vec![r - dx.abs(), -r + dx.abs()];
-                dys.dedup();
-                for &dy in dys.iter() {
-                    let candidate_p = Point::new(
-                        (p.x() + dx as f64 + 0.5).floor(),
-                        (p.y() + dy as f64 + 0.5).floor(),
-                    );
-                    moved[from] = solution[from] != candidate_p;
-                    if is_allowed_distance_point_move(
-                        from,
-                        &candidate_p,
-                        &solution,
-                        &input.figure.vertices,
-                        &out_edges,
-                        &input.hole,
-                        input.epsilon,
-                        &determined,
-                        &moved,
-                    ) {
-                        // if dx != 0 || dy != 0 {
-                        //     eprintln!("move: {} {:?}", from, candidate_p - solution[from]);
-                        // }
-                        solution[from] = candidate_p;
-                        ok = true;
-                        break 'outer_loop;
+            }
+        }
+        // 全ての辺の長さが範囲に入っていれば収束した
+        if touched_vertices.is_empty() {
+            break;
+        }
+        for &u in touched_vertices.iter() {
+            if !does_point_fit_in_hole(&ps[u], &input.hole) {
+                ps[u] = closest_point_on_hole(&ps[u], &input.hole);
+            }
+        }
+    }
+
+    // 動いた頂点を格子点に丸める。
+    // 始点の隣は射影で動かなくても始点との辺が穴に入るとは限らないので、丸めるときに確かめる
+    let mut fixed: Vec<bool> = (0..n).map(|i| ps[i] == solution[i]).collect();
+    fixed[start] = true;
+    for &w in out_edges[start].iter() {
+        fixed[w] = false;
+    }
+    let (mut result, _) = round_to_lattice(
+        start,
+        solution,
+        &ps,
+        fixed,
+        input,
+        out_edges,
+        &determined_orders[start],
+        REPAIR_MAX_UNPLACED,
+    );
+
+    // 頂点 v につながる辺の (違反の数, 違反の大きさの和)
+    // 長さの違反だけで bound より少なくならなければ、穴の判定をせずに None を返す
+    let vertex_violation =
+        |result: &[Point], v: usize, bound: (usize, f64)| -> Option<(usize, f64)> {
+            let (count, sum) = out_edges[v]
+                .iter()
+                .filter_map(|&w| length_violation(result, v, w))
+                .fold((0, 0.0), |(c, s), x| (c + 1, s + x));
+            if count > bound.0 || (count == bound.0 && sum >= bound.1) {
+                return None;
+            }
+            let n_outside = out_edges[v]
+                .iter()
+                .filter(|&&w| {
+                    length_violation(result, v, w).is_none()
+                        && !does_line_fit_in_hole(&result[v], &result[w], &input.hole)
+                })
+                .count();
+            Some((count + n_outside, sum + n_outside as f64))
+        };
+    // 違反の残る頂点を、違反の数、違反の大きさの順に小さくなる近くの格子点へ動かす。
+    // 動かすたびに全体の違反が減るので、どの頂点も動かなくなれば終わる
+    let mut n_searched = 0;
+    loop {
+        let mut changed = false;
+        for &v in determined_orders[start].iter() {
+            if v == start {
+                continue;
+            }
+            let mut best = vertex_violation(&result, v, (usize::MAX, f64::INFINITY)).unwrap();
+            if best.0 == 0 {
+                continue;
+            }
+            if search_limit.is_some_and(|limit| n_searched >= limit) {
+                break;
+            }
+            n_searched += 1;
+            let center = result[v];
+            let mut best_p = center;
+            for dx in -REPAIR_SEARCH_RADIUS..=REPAIR_SEARCH_RADIUS {
+                for dy in -REPAIR_SEARCH_RADIUS..=REPAIR_SEARCH_RADIUS {
+                    let p = center + Point::new(dx as f64, dy as f64);
+                    if p == center {
+                        continue;
+                    }
+                    result[v] = p;
+                    if let Some(violation) = vertex_violation(&result, v, best) {
+                        if violation.0 < best.0 || (violation.0 == best.0 && violation.1 < best.1) {
+                            best = violation;
+                            best_p = p;
+                        }
                     }
                 }
             }
+            result[v] = best_p;
+            changed |= best_p != center;
         }
-        if !ok {
-            return None;
+        if !changed {
+            break;
         }
     }
 
-    // if !does_valid_pose(
-    // &solution,
-    // &input.figure,
-    // &input.hole,
-    // input.epsilon,
-    // &vec![],
-    // None,
-    // ) {
-    // return None;
-    // }
-
-    return Some(solution);
+    let violated_edges = violated_edges(&result);
+    Repair {
+        solution: result,
+        violated_edges,
+    }
 }
 
-fn is_allowed_distance_point_move(
-    index: usize,
-    p: &Point,
+// 頂点を order の順に格子点に丸める。fixed の頂点は動かさない。
+// 丸め終えた隣の頂点との辺が長さも穴も満たす格子点を、元の位置、丸め終えた隣の頂点と同じだけ平行移動した位置、
+// targets に近い格子点の順に探し、無ければ targets を単に丸める。
+// 格子点の上で合っていた辺を崩さないように元の位置を先に試す。始点以外の元の位置どうしの辺は見ない。
+// 見つからなかった頂点が max_unplaced より多くなれば、残りは単に丸める。(丸めたポーズ, 見つからなかった頂点の数) を返す
+#[allow(clippy::too_many_arguments)]
+fn round_to_lattice(
+    start: usize,
     solution: &[Point],
-    original_vertices: &[Point],
+    targets: &[Point],
+    mut fixed: Vec<bool>,
+    input: &Input,
     out_edges: &[Vec<usize>],
-    hole: &Polygon,
-    epsilon: i64,
-    determined: &[bool],
-    moved: &[bool],
-) -> bool {
-    let ok1 = out_edges[index].iter().all(|&dst| {
-        (!moved[index] && !moved[dst])
-            || !determined[dst]
-            || is_allowed_distance(
-                &p,
-                &solution[dst],
-                &original_vertices[index],
-                &original_vertices[dst],
-                epsilon,
-                false,
+    order: &[usize],
+    max_unplaced: usize,
+) -> (Vec<Point>, usize) {
+    let mut result = solution.to_vec();
+    let mut n_unplaced = 0;
+    for &v in order.iter() {
+        if fixed[v] {
+            continue;
+        }
+        fixed[v] = true;
+        let base = Point::new(targets[v].x().round(), targets[v].y().round());
+        if n_unplaced > max_unplaced {
+            result[v] = base;
+            continue;
+        }
+        let mut nearby = vec![];
+        for dx in -REPAIR_ROUND_RADIUS..=REPAIR_ROUND_RADIUS {
+            for dy in -REPAIR_ROUND_RADIUS..=REPAIR_ROUND_RADIUS {
+                nearby.push(base + Point::new(dx as f64, dy as f64));
+            }
+        }
+        nearby.sort_by(|a, b| {
+            squared_distance(a, &targets[v])
+                .partial_cmp(&squared_distance(b, &targets[v]))
+                .unwrap()
+        });
+        let candidates = std::iter::once(solution[v])
+            .chain(
+                out_edges[v]
+                    .iter()
+                    .filter(|&&w| fixed[w])
+                    .map(|&w| solution[v] + (result[w] - solution[w])),
             )
-    });
-    if !ok1 {
-        return false;
-    }
-    let ok2 = out_edges[index].iter().all(|&dst| {
-        (!moved[index] && !moved[dst])
-            || !determined[dst]
-            || does_line_fit_in_hole(&p, &solution[dst], hole)
-    });
-    if !ok2 {
-        return false;
+            .chain(nearby);
+        let found = candidates.into_iter().find(|p| {
+            out_edges[v].iter().all(|&w| {
+                !fixed[w]
+                    || (*p == solution[v] && w != start && result[w] == solution[w])
+                    || (is_allowed_distance(
+                        p,
+                        &result[w],
+                        &input.figure.vertices[v],
+                        &input.figure.vertices[w],
+                        input.epsilon,
+                        false,
+                    ) && does_line_fit_in_hole(p, &result[w], &input.hole))
+            })
+        });
+        result[v] = found.unwrap_or_else(|| {
+            n_unplaced += 1;
+            base
+        });
     }
-    return true;
+    (result, n_unplaced)
+}
+
+// 穴の境界上で p に最も近い点
+fn closest_point_on_hole(p: &Point, hole: &Polygon) -> Point {
+    let mut closest = *p;
+    let mut closest_sd = f64::INFINITY;
+    for line in hole.exterior().lines() {
+        let a = Point::from(line.start);
+        let d = Point::from(line.end) - a;
+        let t = ((*p - a).dot(d) / d.dot(d)).clamp(0.0, 1.0);
+        let q = a + d * t;
+        let sd = squared_distance(p, &q);
+        if sd < closest_sd {
+            closest_sd = sd;
+            closest = q;
+        }
+    }
+    closest
+}
+
+#[test]
+fn test_fix_allowed_distance_violation() {
+    let input = crate::inout::parse_input(
+        r#"{"hole":[[0,0],[20,0],[20,20],[0,20]],"epsilon":0,"figure":{"edges":[[0,1],[1,2]],"vertices":[[0,0],[5,0],[10,0]]},"bonuses":[]}"#,
+    );
+    let n = input.figure.vertices.len();
+    let out_edges = make_out_edges(&input.figure.edges, n);
+    let orders: Vec<Vec<usize>> = (0..n)
+        .map(|i| make_determined_order(&out_edges, Some(i)))
+        .collect();
+
+    // 端の頂点を 3 ずらすと、残りの頂点が付いてくる
+    let mut solution = input.figure.vertices.clone();
+    solution[0] = Point::new(0.0, 3.0);
+    let repair = fix_allowed_distance_violation(0, &solution, &input, &out_edges, &orders, None);
+    assert!(repair.is_valid());
+    assert_eq!(repair.solution[0], Point::new(0.0, 3.0));
+    assert!(does_valid_pose(
+        &repair.solution,
+        &input.figure,
+        &input.hole,
+        input.epsilon,
+        &vec![],
+        None
+    ));
+
+    // 4x4 の穴には長さ 6 の辺が入らないので、直せなかった辺が返る
+    let input = crate::inout::parse_input(
+        r#"{"hole":[[0,0],[4,0],[4,4],[0,4]],"epsilon":0,"figure":{"edges":[[0,1]],"vertices":[[1,1],[7,1]]},"bonuses":[]}"#,
+    );
+    let out_edges = make_out_edges(&input.figure.edges, 2);
+    let orders = vec![vec![0, 1], vec![1, 0]];
+    let repair =
+        fix_allowed_distance_violation(0, &input.figure.vertices, &input, &out_edges, &orders, None);
+    assert_eq!(repair.violated_edges, vec![0]);
+    assert_eq!(repair.solution[0], Point::new(1.0, 1.0));
+    assert_eq!(repair.clone().into_valid(), None);
+
+    // 動かした頂点とつながっていない辺でも、呼ぶ前から違反していれば返る
+    let input = crate::inout::parse_input(
+        r#"{"hole":[[0,0],[4,0],[4,4],[0,4]],"epsilon":0,"figure":{"edges":[[0,1],[2,3]],"vertices":[[1,1],[3,1],[1,3],[7,3]]},"bonuses":[]}"#,
+    );
+    let out_edges = make_out_edges(&input.figure.edges, 4);
+    let orders: Vec<Vec<usize>> = (0..4)
+        .map(|i| make_determined_order(&out_edges, Some(i)))
+        .collect();
+    let mut solution = input.figure.vertices.clone();
+    solution[0] = Point::new(1.0, 2.0);
+    let repair = fix_allowed_distance_violation(0, &solution, &input, &out_edges, &orders, None);
+    assert_eq!(repair.violated_edges, vec![1]);
+    assert_eq!(repair.solution[0], Point::new(1.0, 2.0));
+    assert_eq!(squared_distance(&repair.solution[0], &repair.solution[1]), 4.0);
+
+    // 端の頂点を隣の頂点に重ねても、元の辺の向きに引き離して直す
+    let input = crate::inout::parse_input(
+        r#"{"hole":[[0,0],[20,0],[20,20],[0,20]],"epsilon":0,"figure":{"edges":[[0,1],[1,2]],"vertices":[[2,10],[7,10],[12,10]]},"bonuses":[]}"#,
+    );
+    let out_edges = make_out_edges(&input.figure.edges, 3);
+    let orders: Vec<Vec<usize>> = (0..3)
+        .map(|i| make_determined_order(&out_edges, Some(i)))
+        .collect();
+    let mut solution = input.figure.vertices.clone();
+    solution[0] = Point::new(7.0, 10.0);
+    let repair = fix_allowed_distance_violation(0, &solution, &input, &out_edges, &orders, None);
+    assert!(repair.is_valid());
+    assert_eq!(repair.solution[0], Point::new(7.0, 10.0));
 }
 
 // 橋でグラフを分割する。(橋の集合, 各連結成分の頂点集合) が返される。
@@ -982,3 +1627,89 @@ pub fn maximum_flow(
     }
     total
 }
+
+// 割当問題をハンガリアン法 (ポテンシャル付き, O(n^2 m)) で解く。
+// cost は n 行 m 列 (n <= m) で、各行に相異なる列を 1 つずつ割り当てて cost の和を最小にする。
+// 行ごとに割り当てた列を返す
+pub fn solve_assignment(cost: &[Vec<f64>]) -> Vec<usize> {
+    let n = cost.len();
+    if n == 0 {
+        return vec![];
+    }
+    let m = cost[0].len();
+    assert!(n <= m);
+    // 添字は 1 始まりで、列 0 は番兵
+    let mut u = vec![0.0; n + 1];
+    let mut v = vec![0.0; m + 1];
+    // 列に割り当てた行。0 は未割当
+    let mut p = vec![0; m + 1];
+    let mut way = vec![0; m + 1];
+    for i in 1..=n {
+        p[0] = i;
+        let mut j0 = 0;
+        let mut minv = vec![f64::INFINITY; m + 1];
+        let mut used = vec![false; m + 1];
+        loop {
+            used[j0] = true;
+            let i0 = p[j0];
+            let mut delta = f64::INFINITY;
+            let mut j1 = 0;
+            for j in 1..=m {
+                if used[j] {
+                    continue;
+                }
+                let cur = cost[i0 - 1][j - 1] - u[i0] - v[j];
+                if cur < minv[j] {
+                    minv[j] = cur;
+                    way[j] = j0;
+                }
+                if minv[j] < delta {
+                    delta = minv[j];
+                    j1 = j;
+                }
+            }
+            for j in 0..=m {
+                if used[j] {
+                    u[p[j]] += delta;
+                    v[j] -= delta;
+                } else {
+                    minv[j] -= delta;
+                }
+            }
+            j0 = j1;
+            if p[j0] == 0 {
+                break;
+            }
+        }
+        // 増加路に沿って割当を入れ替える
+        while j0 != 0 {
+            let j1 = way[j0];
+            p[j0] = p[j1];
+            j0 = j1;
+        }
+    }
+    let mut assignment = vec![0; n];
+    for j in 1..=m {
+        if p[j] != 0 {
+            assignment[p[j] - 1] = j - 1;
+        }
+    }
+    assignment
+}
+
+#[test]
+fn test_solve_assignment() {
+    // 貪欲に行 0 へ列 0 を割り当てると 1 + 10 = 11 だが、最適は 2 + 2 = 4
+    let cost = vec![vec![1.0, 2.0], vec![2.0, 10.0]];
+    assert_eq!(solve_assignment(&cost), vec![1, 0]);
+
+    // 行が列より少ないときは使わない列が残る
+    let cost = vec![
+        vec![4.0, 1.0, 3.0, 9.0],
+        vec![2.0, 0.0, 5.0, 9.0],
+        vec![3.0, 2.0, 2.0, 9.0],
+    ];
+    let assignment = solve_assignment(&cost);
+    assert_eq!(assignment, vec![1, 0, 2]);
+    assert_eq!(solve_assignment(&[]), Vec::<usize>::new());
+}
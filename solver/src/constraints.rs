@@ -0,0 +1,135 @@
+use crate::common::*;
+use geo::algorithm::contains::Contains;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+// {"fixed": [{"vertex": 3, "position": [10, 20]}],
+//  "regions": [{"vertex": 5, "polygon": [[0, 0], [10, 0], [10, 10], [0, 10]]}]}
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ConstraintsJSON {
+    #[serde(default)]
+    pub fixed: Vec<FixedJSON>,
+    #[serde(default)]
+    pub regions: Vec<RegionJSON>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FixedJSON {
+    pub vertex: usize,
+    pub position: Vec<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RegionJSON {
+    pub vertex: usize,
+    pub polygon: Vec<Vec<i64>>,
+}
+
+// 頂点ごとの位置の制約。固定された頂点は動かさず、領域が指定された頂点はその中 (境界を含む) にだけ置く
+#[derive(Debug, Clone, Default)]
+pub struct Constraints {
+    fixed: HashMap<usize, Point>,
+    regions: HashMap<usize, Polygon>,
+}
+
+impl Constraints {
+    pub fn parse(data: &str) -> Constraints {
+        let j: ConstraintsJSON =
+            serde_json::from_str(data).expect("failed to parse constraints as JSON");
+        let fixed = j
+            .fixed
+            .iter()
+            .map(|f| {
+                let p = Point::new(f.position[0] as f64, f.position[1] as f64);
+                (f.vertex, p)
+            })
+            .collect();
+        let regions = j
+            .regions
+            .iter()
+            .map(|r| {
+                let ps: Vec<(f64, f64)> = r
+                    .polygon
+                    .iter()
+                    .map(|p| (p[0] as f64, p[1] as f64))
+                    .collect();
+                (r.vertex, Polygon::new(geo::LineString::from(ps), vec![]))
+            })
+            .collect();
+        Constraints { fixed, regions }
+    }
+
+    pub fn load(path: &Path) -> Constraints {
+        let data = std::fs::read_to_string(path)
+            .unwrap_or_else(|e| panic!("can't load {}: {}", path.display(), e));
+        Constraints::parse(&data)
+    }
+
+    pub fn fixed_point(&self, i: usize) -> Option<Point> {
+        self.fixed.get(&i).copied()
+    }
+
+    pub fn is_fixed(&self, i: usize) -> bool {
+        self.fixed.contains_key(&i)
+    }
+
+    // 探索を始めるのに使う固定頂点 (番号が最小のもの)
+    pub fn first_fixed_vertex(&self) -> Option<usize> {
+        self.fixed.keys().min().copied()
+    }
+
+    // 固定されていない頂点の一覧
+    pub fn free_vertices(&self, n: usize) -> Vec<usize> {
+        (0..n).filter(|&i| !self.is_fixed(i)).collect()
+    }
+
+    // 頂点 i を p に置いてよいか
+    pub fn allows(&self, i: usize, p: &Point) -> bool {
+        if let Some(q) = self.fixed.get(&i) {
+            if p != q {
+                return false;
+            }
+        }
+        if let Some(region) = self.regions.get(&i) {
+            if !region.contains(p) && !region.exterior().contains(p) {
+                return false;
+            }
+        }
+        true
+    }
+
+    pub fn is_satisfied(&self, vertices: &[Point]) -> bool {
+        self.fixed
+            .keys()
+            .chain(self.regions.keys())
+            .all(|&i| self.allows(i, &vertices[i]))
+    }
+
+    pub fn check_vertex_count(&self, n: usize) {
+        for &i in self.fixed.keys().chain(self.regions.keys()) {
+            if i >= n {
+                panic!(
+                    "constraint for vertex {} is given, but the figure has {} vertices",
+                    i, n
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn test_constraints() {
+    let constraints = Constraints::parse(
+        r#"{"fixed":[{"vertex":1,"position":[3,4]}],"regions":[{"vertex":2,"polygon":[[0,0],[10,0],[10,10],[0,10]]}]}"#,
+    );
+    assert!(constraints.is_fixed(1));
+    assert_eq!(constraints.first_fixed_vertex(), Some(1));
+    assert_eq!(constraints.free_vertices(3), vec![0, 2]);
+    assert!(constraints.allows(0, &Point::new(100.0, 100.0)));
+    assert!(constraints.allows(1, &Point::new(3.0, 4.0)));
+    assert!(!constraints.allows(1, &Point::new(3.0, 5.0)));
+    assert!(constraints.allows(2, &Point::new(10.0, 5.0)));
+    assert!(!constraints.allows(2, &Point::new(11.0, 5.0)));
+    assert!(Constraints::default().is_satisfied(&[Point::new(0.0, 0.0)]));
+}
@@ -0,0 +1,154 @@
+use crate::audit::{check_pose, list_ids, try_load_pose_json};
+use crate::inout::*;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+// solutions.jsonl などの記録から問題ごとに妥当で dislike が最小のポーズを選び、
+// solutions_dir にあるものより真に良ければ書き込む。書き込んだ数を返す
+pub fn import_records(
+    records: &[SolutionRecordJSON],
+    problems_dir: &Path,
+    solutions_dir: &Path,
+) -> usize {
+    let mut by_problem: BTreeMap<u32, Vec<&SolutionRecordJSON>> = BTreeMap::new();
+    for record in records.iter() {
+        match record.problem_id.parse() {
+            Ok(id) => by_problem.entry(id).or_default().push(record),
+            Err(_) => eprintln!("invalid ProblemId {}", record.problem_id),
+        }
+    }
+    let mut n_imported = 0;
+    for (&id, records) in by_problem.iter() {
+        let problem_path = problems_dir.join(format!("{}.problem", id));
+        if !problem_path.exists() {
+            eprintln!("{}: {} does not exist", id, problem_path.display());
+            continue;
+        }
+        let input = load_input(&problem_path);
+        // 記録されている Dislikes は信用せず、検証して計算し直す。同じ dislike ならボーナスを使わないものを選ぶ
+        let best = records
+            .iter()
+            .filter_map(|r| {
+                let dislike = check_pose(id, &r.pose, &input, problems_dir).ok()?;
+                let n_bonuses = r.pose.bonuses.as_ref().map_or(0, |b| b.len());
+                Some((dislike, n_bonuses, r))
+            })
+            .min_by(|a, b| (a.0, a.1).partial_cmp(&(b.0, b.1)).unwrap());
+        let (dislike, _, record) = match best {
+            Some(best) => best,
+            None => {
+                eprintln!("{}: no valid poses in {} records", id, records.len());
+                continue;
+            }
+        };
+        let solution_path = solutions_dir.join(format!("{}.solution", id));
+        let current = try_load_pose_json(&solution_path)
+            .and_then(|pose_json| check_pose(id, &pose_json, &input, problems_dir))
+            .ok();
+        match current {
+            Some(current) if current <= dislike => {
+                eprintln!("{}: kept {} (best record is {})", id, current, dislike);
+            }
+            _ => {
+                eprintln!("{}: imported {} (was {:?})", id, dislike, current);
+                write_file_atomically(
+                    &solution_path,
+                    &serde_json::to_string(&record.pose).unwrap(),
+                );
+                n_imported += 1;
+            }
+        }
+    }
+    n_imported
+}
+
+// solutions_dir の妥当な解を solutions.jsonl と同じ形にする
+pub fn export_records(
+    problems_dir: &Path,
+    solutions_dir: &Path,
+    commit_params: &str,
+) -> Vec<SolutionRecordJSON> {
+    let mut records = vec![];
+    for id in list_ids(solutions_dir, "solution") {
+        let problem_path = problems_dir.join(format!("{}.problem", id));
+        if !problem_path.exists() {
+            eprintln!("{}: {} does not exist", id, problem_path.display());
+            continue;
+        }
+        let input = load_input(&problem_path);
+        let solution_path = solutions_dir.join(format!("{}.solution", id));
+        let checked = try_load_pose_json(&solution_path).and_then(|pose_json| {
+            check_pose(id, &pose_json, &input, problems_dir).map(|dislike| (pose_json, dislike))
+        });
+        let (pose, dislike) = match checked {
+            Ok(ret) => ret,
+            Err(note) => {
+                eprintln!("{}: {}, skipped", id, note);
+                continue;
+            }
+        };
+        // 頂点が乗っているボーナスの位置は、そのボーナスを手に入れたことになる
+        let vertices = pose_json_to_vertices(&pose);
+        let unlock_bonuses = input
+            .bonuses
+            .iter()
+            .filter(|b| vertices.contains(&b.position))
+            .map(|b| BonusInJSON {
+                position: vec![b.position.x() as i64, b.position.y() as i64],
+                bonus: b.bonus.clone(),
+                problem: b.problem,
+            })
+            .collect();
+        let used_bonuses = pose
+            .bonuses
+            .iter()
+            .flatten()
+            .map(|b| BonusOutJSON {
+                bonus: b.bonus.clone(),
+                problem: b.problem,
+                edge: b.edge.clone(),
+            })
+            .collect();
+        records.push(SolutionRecordJSON {
+            problem_id: id.to_string(),
+            commit_params: Some(commit_params.to_string()),
+            dislikes: dislike as i64,
+            used_bonuses,
+            unlock_bonuses,
+            pose,
+        });
+    }
+    records
+}
+
+#[test]
+fn test_import_records() {
+    use crate::common::Point;
+    let dir = std::env::temp_dir().join(format!("dump-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("1.problem"),
+        r#"{"hole":[[0,0],[20,0],[20,20],[0,20]],"epsilon":0,"figure":{"edges":[[0,1]],"vertices":[[0,0],[4,0]]},"bonuses":[]}"#,
+    )
+    .unwrap();
+    let records: Vec<SolutionRecordJSON> = [
+        r#"{"ProblemId":"1","Dislikes":1312,"Pose":{"vertices":[[0,0],[4,0]]}}"#,
+        // 記録上の dislike は小さいが妥当でない
+        r#"{"ProblemId":"1","Dislikes":0,"Pose":{"vertices":[[0,0],[20,20]]}}"#,
+        r#"{"ProblemId":"1","Dislikes":656,"Pose":{"vertices":[[8,10],[12,10]]}}"#,
+    ]
+    .iter()
+    .map(|s| serde_json::from_str(s).unwrap())
+    .collect();
+    assert_eq!(import_records(&records[..1], &dir, &dir), 1);
+    assert_eq!(import_records(&records, &dir, &dir), 1);
+    assert_eq!(import_records(&records, &dir, &dir), 0);
+    assert_eq!(
+        load_pose(&dir.join("1.solution")),
+        vec![Point::new(8.0, 10.0), Point::new(12.0, 10.0)]
+    );
+    let exported = export_records(&dir, &dir, "test:");
+    assert_eq!(exported.len(), 1);
+    assert_eq!(exported[0].dislikes, 656);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
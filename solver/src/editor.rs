@@ -0,0 +1,393 @@
+use crate::common::*;
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const EDITOR_HTML: &str = include_str!("editor.html");
+
+// ブラウザから送られてくる操作
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Command {
+    // 頂点を (x, y) に固定する
+    Pin { vertex: usize, x: i64, y: i64 },
+    Unpin { vertex: usize },
+    // 頂点を (x, y) に動かす (固定はしない)
+    Move { vertex: usize, x: i64, y: i64 },
+    Climb { millis: Option<u64> },
+    Reset,
+}
+
+#[derive(Debug, Serialize)]
+struct ReportJSON {
+    valid: bool,
+    dislike: f64,
+    stretched_edges: Vec<(usize, f64)>,
+    outside_edges: Vec<usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct StateJSON {
+    hole: Vec<Vec<i64>>,
+    edges: Vec<Vec<usize>>,
+    epsilon: i64,
+    vertices: Vec<Vec<i64>>,
+    pinned: Vec<usize>,
+    report: ReportJSON,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+}
+
+fn to_json_point(p: &Point) -> Vec<i64> {
+    vec![p.x() as i64, p.y() as i64]
+}
+
+// 編集中のポーズと固定された頂点
+pub struct Session {
+    input: Input,
+    initial_pose: Vec<Point>,
+    pose: Vec<Point>,
+    pinned: Vec<bool>,
+    out_edges: Vec<Vec<usize>>,
+    orders: Vec<Vec<usize>>,
+    climb_time: Duration,
+    rng: SmallRng,
+}
+
+impl Session {
+    pub fn new(input: Input, pose: Vec<Point>, climb_time: Duration) -> Session {
+        let n = pose.len();
+        let out_edges = make_out_edges(&input.figure.edges, n);
+        let orders = (0..n)
+            .map(|i| make_determined_order(&out_edges, Some(i)))
+            .collect();
+        Session {
+            input,
+            initial_pose: pose.clone(),
+            pose,
+            pinned: vec![false; n],
+            out_edges,
+            orders,
+            climb_time,
+            rng: SmallRng::from_entropy(),
+        }
+    }
+
+    fn apply(&mut self, command: Command) -> Option<String> {
+        let n = self.pose.len();
+        let vertex = match command {
+            Command::Pin { vertex, .. }
+            | Command::Unpin { vertex }
+            | Command::Move { vertex, .. } => Some(vertex),
+            _ => None,
+        };
+        if let Some(i) = vertex {
+            if i >= n {
+                return Some(format!("vertex {} is out of range", i));
+            }
+        }
+        match command {
+            Command::Pin { vertex, x, y } => {
+                self.pinned[vertex] = true;
+                self.place(vertex, Point::new(x as f64, y as f64))
+            }
+            Command::Unpin { vertex } => {
+                self.pinned[vertex] = false;
+                None
+            }
+            Command::Move { vertex, x, y } => {
+                if self.pinned[vertex] {
+                    return Some(format!("vertex {} is pinned", vertex));
+                }
+                self.place(vertex, Point::new(x as f64, y as f64))
+            }
+            Command::Climb { millis } => {
+                let time = millis.map_or(self.climb_time, Duration::from_millis);
+                self.climb(time);
+                None
+            }
+            Command::Reset => {
+                self.pose = self.initial_pose.clone();
+                self.pinned = vec![false; n];
+                None
+            }
+        }
+    }
+
+    // 頂点 i を p に置き、周りの辺の長さを修復する
+    fn place(&mut self, i: usize, p: Point) -> Option<String> {
+        self.pose[i] = p;
+        let mut message = None;
+        let repair = fix_allowed_distance_violation(
+            i,
+            &self.pose,
+            &self.input,
+            &self.out_edges,
+            &self.orders,
+            None,
+        );
+        // 直しきれなくても違反の残る辺を知らせて、残りは山登りに任せる
+        if self.keeps_pinned(&repair.solution) {
+            if !repair.is_valid() {
+                message = Some(format!(
+                    "fix_allowed_distance_violation: edges {:?} are still violated",
+                    repair.violated_edges
+                ));
+            }
+            self.pose = repair.solution;
+        } else {
+            message = Some("fix_allowed_distance_violation failed".to_string());
+        }
+        self.climb(self.climb_time);
+        message
+    }
+
+    fn keeps_pinned(&self, pose: &[Point]) -> bool {
+        (0..pose.len()).all(|j| !self.pinned[j] || pose[j] == self.pose[j])
+    }
+
+    // 辺の長さの超過と穴からのはみ出しに対するペナルティ
+    fn edge_penalty(&self, pose: &[Point], e: &Edge) -> f64 {
+        let ratio = calc_distance_ratio(
+            &pose[e.v],
+            &pose[e.w],
+            &self.input.figure.vertices[e.v],
+            &self.input.figure.vertices[e.w],
+        );
+        let mut penalty = (ratio.abs() - self.input.epsilon as f64 / 1000000.0).max(0.0);
+        if !does_line_fit_in_hole(&pose[e.v], &pose[e.w], &self.input.hole) {
+            penalty += 1.0;
+        }
+        penalty
+    }
+
+    fn vertex_penalty(&self, pose: &[Point], i: usize) -> f64 {
+        self.out_edges[i]
+            .iter()
+            .map(|&j| self.edge_penalty(pose, &Edge::new(i, j)))
+            .sum()
+    }
+
+    // 固定されていない頂点だけを動かす山登り。ペナルティ、dislike の順に小さくする
+    fn climb(&mut self, time: Duration) {
+        let free: Vec<usize> = (0..self.pose.len()).filter(|&i| !self.pinned[i]).collect();
+        if free.is_empty() {
+            return;
+        }
+        let mut pose = self.pose.clone();
+        let mut dislike = calculate_dislike(&pose, &self.input.hole);
+        let start_at = Instant::now();
+        let mut iter = 0;
+        loop {
+            iter += 1;
+            if iter % 100 == 0 && Instant::now() - start_at >= time {
+                break;
+            }
+            // 制約を満たしていない頂点があればそれを優先して動かす
+            let broken: Vec<usize> = free
+                .iter()
+                .copied()
+                .filter(|&i| self.vertex_penalty(&pose, i) > 0.0)
+                .collect();
+            let candidates = if broken.is_empty() { &free } else { &broken };
+            let i = candidates[self.rng.gen_range(0..candidates.len())];
+            let dx = self.rng.gen_range(-2..=2) as f64;
+            let dy = self.rng.gen_range(-2..=2) as f64;
+            if dx == 0.0 && dy == 0.0 {
+                continue;
+            }
+            let old = pose[i];
+            let old_penalty = self.vertex_penalty(&pose, i);
+            pose[i] = Point::new(old.x() + dx, old.y() + dy);
+            let new_penalty = self.vertex_penalty(&pose, i);
+            let new_dislike = calculate_dislike(&pose, &self.input.hole);
+            if new_penalty < old_penalty || (new_penalty == old_penalty && new_dislike <= dislike) {
+                dislike = new_dislike;
+            } else {
+                pose[i] = old;
+            }
+        }
+        self.pose = pose;
+    }
+
+    fn state(&self, message: Option<String>) -> StateJSON {
+        let report = make_pose_report(&self.pose, &self.input);
+        StateJSON {
+            hole: self
+                .input
+                .hole
+                .exterior()
+                .points_iter()
+                .skip(1)
+                .map(|p| to_json_point(&p))
+                .collect(),
+            edges: self
+                .input
+                .figure
+                .edges
+                .iter()
+                .map(|e| vec![e.v, e.w])
+                .collect(),
+            epsilon: self.input.epsilon,
+            vertices: self.pose.iter().map(to_json_point).collect(),
+            pinned: (0..self.pose.len()).filter(|&i| self.pinned[i]).collect(),
+            report: ReportJSON {
+                valid: report.is_valid(),
+                dislike: report.dislike,
+                stretched_edges: report.stretched_edges,
+                outside_edges: report.outside_edges,
+            },
+            message,
+        }
+    }
+}
+
+// HTTP で編集画面と現在の状態を返し、/ws の WebSocket で操作を受け付ける
+pub fn serve(session: Session, port: u16) {
+    let listener = TcpListener::bind(("127.0.0.1", port))
+        .unwrap_or_else(|e| panic!("can't listen on port {}: {}", port, e));
+    eprintln!("editor: http://127.0.0.1:{}/", port);
+    let session = Arc::new(Mutex::new(session));
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("accept failed: {}", e);
+                continue;
+            }
+        };
+        let session = session.clone();
+        std::thread::spawn(move || {
+            if is_websocket_request(&stream) {
+                handle_websocket(stream, session);
+            } else if let Err(e) = handle_http(stream, session) {
+                eprintln!("http error: {}", e);
+            }
+        });
+    }
+}
+
+// リクエストヘッダを読み進めずに覗いて WebSocket へのアップグレードかどうかを調べる
+fn is_websocket_request(stream: &TcpStream) -> bool {
+    let mut buf = [0u8; 4096];
+    for _ in 0..100 {
+        let len = match stream.peek(&mut buf) {
+            Ok(len) => len,
+            Err(_) => return false,
+        };
+        let head = String::from_utf8_lossy(&buf[..len]).to_ascii_lowercase();
+        if head.contains("\r\n\r\n") || len == buf.len() {
+            return head.contains("upgrade: websocket");
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    false
+}
+
+fn handle_http(stream: TcpStream, session: Arc<Mutex<Session>>) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+    }
+    let path = request_line.split_whitespace().nth(1).unwrap_or("/");
+    let (status, content_type, body) = match path {
+        "/" => (
+            "200 OK",
+            "text/html; charset=utf-8",
+            EDITOR_HTML.to_string(),
+        ),
+        "/state" => {
+            let state = session.lock().unwrap().state(None);
+            (
+                "200 OK",
+                "application/json",
+                serde_json::to_string(&state).unwrap(),
+            )
+        }
+        _ => ("404 Not Found", "text/plain", "not found".to_string()),
+    };
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+fn handle_websocket(stream: TcpStream, session: Arc<Mutex<Session>>) {
+    let mut ws = match tungstenite::accept(stream) {
+        Ok(ws) => ws,
+        Err(e) => {
+            eprintln!("websocket handshake failed: {}", e);
+            return;
+        }
+    };
+    let state = session.lock().unwrap().state(None);
+    if ws
+        .send(tungstenite::Message::text(
+            serde_json::to_string(&state).unwrap(),
+        ))
+        .is_err()
+    {
+        return;
+    }
+    loop {
+        let text = match ws.read() {
+            Ok(tungstenite::Message::Text(text)) => text,
+            Ok(tungstenite::Message::Close(_)) | Err(_) => return,
+            Ok(_) => continue,
+        };
+        let state = {
+            let mut session = session.lock().unwrap();
+            let message = match serde_json::from_str::<Command>(&text) {
+                Ok(command) => session.apply(command),
+                Err(e) => Some(format!("invalid command: {}", e)),
+            };
+            session.state(message)
+        };
+        if ws
+            .send(tungstenite::Message::text(
+                serde_json::to_string(&state).unwrap(),
+            ))
+            .is_err()
+        {
+            return;
+        }
+    }
+}
+
+#[test]
+fn test_session_pin() {
+    let input = crate::inout::parse_input(
+        r#"{"hole":[[0,0],[20,0],[20,20],[0,20]],"epsilon":0,"figure":{"edges":[[0,1],[1,2]],"vertices":[[0,0],[4,0],[4,4]]},"bonuses":[]}"#,
+    );
+    let pose = input.figure.vertices.clone();
+    let mut session = Session::new(input, pose, Duration::from_millis(10));
+    session.apply(Command::Pin {
+        vertex: 0,
+        x: 10,
+        y: 10,
+    });
+    assert_eq!(session.pose[0], Point::new(10.0, 10.0));
+    session.apply(Command::Climb { millis: Some(10) });
+    assert_eq!(session.pose[0], Point::new(10.0, 10.0));
+    assert!(session.state(None).pinned == vec![0]);
+    let message = session.apply(Command::Move {
+        vertex: 0,
+        x: 0,
+        y: 0,
+    });
+    assert!(message.is_some());
+}
@@ -0,0 +1,226 @@
+use crate::budget::{Budget, BudgetPlan};
+use crate::common::*;
+use crate::constraints::Constraints;
+use crate::inout::*;
+use crate::solvers;
+use std::path::Path;
+use std::time::Duration;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Improver {
+    Orthgonal,
+    Annealing,
+    Annealing3,
+    PenaltyAnnealing,
+    HillClimbing,
+    Adjust,
+}
+
+impl std::str::FromStr for Improver {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "orthgonal" => Ok(Improver::Orthgonal),
+            "annealing" => Ok(Improver::Annealing),
+            "annealing3" => Ok(Improver::Annealing3),
+            "penalty_annealing" => Ok(Improver::PenaltyAnnealing),
+            "hill_climbing" => Ok(Improver::HillClimbing),
+            "adjust" => Ok(Improver::Adjust),
+            _ => Err(format!("Invalid improver {}", s)),
+        }
+    }
+}
+
+impl Improver {
+    // 制限時間を配分するときの重み
+    fn weight(&self) -> f64 {
+        match self {
+            Improver::Orthgonal | Improver::Adjust => 0.1,
+            Improver::Annealing
+            | Improver::Annealing3
+            | Improver::PenaltyAnnealing
+            | Improver::HillClimbing => 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ImproveOptions {
+    pub improvers: Vec<Improver>,
+    // 1 問あたりの制限時間。焼きなまし系に多く配分する
+    pub time_limit: Duration,
+    // 指定されていれば time_limit の代わりに使う
+    pub iteration_limit: Option<u64>,
+    pub seed: Option<u64>,
+    pub initial_temperature: f64,
+    pub corner_move_rate: f64,
+}
+
+// 保存されているポーズから改善器を順に適用し、途中も含めて dislike が最小の妥当なポーズを返す
+pub fn improve(input: &Input, pose: Vec<Point>, options: &ImproveOptions) -> (Vec<Point>, f64) {
+    let constraints = Constraints::default();
+    let used_bonus_types = vec![];
+    let mut best_dislike = calculate_dislike(&pose, &input.hole);
+    let mut best = pose.clone();
+    let mut current = pose;
+    let weights = options
+        .improvers
+        .iter()
+        .enumerate()
+        .map(|(i, improver)| (i.to_string(), improver.weight()))
+        .collect();
+    let total_budget = match options.iteration_limit {
+        Some(n) => Budget::with_iterations(n),
+        None => Budget::new(options.time_limit),
+    };
+    let mut plan = BudgetPlan::new(total_budget, weights);
+    for (i, &improver) in options.improvers.iter().enumerate() {
+        let budget = plan.stage(&i.to_string());
+        let next = match improver {
+            Improver::Orthgonal => {
+                let mut input2 = input.clone();
+                input2.figure.vertices = current.clone();
+                solvers::orthgonal::solve(&input2, &budget)
+                    .0
+                    .map(|(s, _)| s)
+            }
+            Improver::Annealing => Some(
+                solvers::annealing::solve(
+                    input,
+                    current.clone(),
+                    &budget,
+                    options.seed,
+                    options.initial_temperature,
+                    options.corner_move_rate,
+                    &constraints,
+                    None,
+                )
+                .0,
+            ),
+            Improver::Annealing3 => Some(
+                solvers::annealing3::solve(
+                    input,
+                    current.clone(),
+                    &budget,
+                    options.seed,
+                    options.initial_temperature,
+                    options.corner_move_rate,
+                    &constraints,
+                    None,
+                )
+                .0,
+            ),
+            Improver::PenaltyAnnealing => Some(
+                solvers::penalty_annealing::solve(
+                    input,
+                    current.clone(),
+                    &budget,
+                    options.seed,
+                    options.initial_temperature,
+                    &constraints,
+                    None,
+                )
+                .0,
+            ),
+            Improver::HillClimbing => Some(
+                solvers::hill_climbing::solve(
+                    input,
+                    current.clone(),
+                    &budget,
+                    options.seed,
+                    options.corner_move_rate,
+                    &constraints,
+                    None,
+                )
+                .0,
+            ),
+            Improver::Adjust => Some(
+                solvers::adjust::solve(
+                    input,
+                    &used_bonus_types,
+                    current.clone(),
+                    &budget,
+                    &constraints,
+                )
+                .0,
+            ),
+        };
+        let next = match next {
+            Some(next) => next,
+            None => continue,
+        };
+        // 妥当でない結果は次の段に渡さない
+        if !does_valid_pose(
+            &next,
+            &input.figure,
+            &input.hole,
+            input.epsilon,
+            &used_bonus_types,
+            None,
+        ) {
+            eprintln!("{:?}: invalid pose, discarded", improver);
+            continue;
+        }
+        let dislike = calculate_dislike(&next, &input.hole);
+        eprintln!("{:?}: dislike = {}", improver, dislike);
+        if dislike < best_dislike {
+            best_dislike = dislike;
+            best = next.clone();
+        }
+        current = next;
+    }
+    (best, best_dislike)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImproveResult {
+    Improved,
+    NotImproved,
+    // ボーナスを使っている、あるいは元のポーズが妥当でないので改善しなかった
+    Skipped,
+}
+
+// solution_path のポーズを改善し、真に良くなった場合だけ書き戻す
+pub fn improve_file(
+    problem_path: &Path,
+    solution_path: &Path,
+    options: &ImproveOptions,
+) -> ImproveResult {
+    let input = load_input(problem_path);
+    let pose_json = load_pose_json(solution_path);
+    if pose_json.bonuses.as_ref().is_some_and(|b| !b.is_empty()) {
+        eprintln!("{}: uses bonuses, skipped", solution_path.display());
+        return ImproveResult::Skipped;
+    }
+    let pose = pose_json_to_vertices(&pose_json);
+    if pose.len() != input.figure.vertices.len()
+        || !does_valid_pose(
+            &pose,
+            &input.figure,
+            &input.hole,
+            input.epsilon,
+            &vec![],
+            None,
+        )
+    {
+        eprintln!("{}: invalid pose, skipped", solution_path.display());
+        return ImproveResult::Skipped;
+    }
+    let dislike = calculate_dislike(&pose, &input.hole);
+    let (improved, improved_dislike) = improve(&input, pose, options);
+    if improved_dislike < dislike {
+        eprintln!(
+            "{}: improved {} -> {}",
+            solution_path.display(),
+            dislike,
+            improved_dislike
+        );
+        let j = vertices_to_pose_json(&improved, &vec![], &None);
+        write_file_atomically(solution_path, &j);
+        ImproveResult::Improved
+    } else {
+        eprintln!("{}: not improved ({})", solution_path.display(), dislike);
+        ImproveResult::NotImproved
+    }
+}
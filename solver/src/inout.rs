@@ -39,8 +39,53 @@ pub struct BonusOutJSON {
     pub edge: Option<Vec<i64>>,
 }
 
+// solutions.jsonl の 1 行
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SolutionRecordJSON {
+    #[serde(rename = "ProblemId")]
+    pub problem_id: String,
+    #[serde(rename = "Commit:Params", skip_serializing_if = "Option::is_none")]
+    pub commit_params: Option<String>,
+    #[serde(rename = "Dislikes")]
+    pub dislikes: i64,
+    #[serde(rename = "UsedBonuses", default)]
+    pub used_bonuses: Vec<BonusOutJSON>,
+    #[serde(rename = "UnlockBonuses", default)]
+    pub unlock_bonuses: Vec<BonusInJSON>,
+    #[serde(rename = "Pose")]
+    pub pose: PoseJSON,
+}
+
+// JSONL を読み、解の記録として読めない行は飛ばす
+pub fn load_solution_records(path: &Path) -> Vec<SolutionRecordJSON> {
+    let data = std::fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("can't load {}: {}", path.display(), e));
+    let mut n_skipped = 0;
+    let records = data
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| {
+            let record = serde_json::from_str(line).ok();
+            if record.is_none() {
+                n_skipped += 1;
+            }
+            record
+        })
+        .collect();
+    if n_skipped > 0 {
+        eprintln!("{}: skipped {} lines that are not solution records", path.display(), n_skipped);
+    }
+    records
+}
+
+/// 問題の JSON を読む
 pub fn parse_input(data: &str) -> Input {
-    let input_json: InputJSON = serde_json::from_str(&data).expect("failed to parse input as JSON");
+    try_parse_input(data).unwrap_or_else(|e| panic!("failed to parse input as JSON: {}", e))
+}
+
+/// 問題の JSON を読む。読めなければエラーを返す
+pub fn try_parse_input(data: &str) -> Result<Input, String> {
+    let input_json: InputJSON = serde_json::from_str(data).map_err(|e| e.to_string())?;
 
     let hole: Vec<(f64, f64)> = input_json
         .hole
@@ -69,27 +114,29 @@ pub fn parse_input(data: &str) -> Input {
         })
         .collect();
 
-    Input {
+    Ok(Input {
         hole: Polygon::new(geo::LineString::from(hole), vec![]),
         figure: Figure { edges, vertices },
         epsilon: input_json.epsilon,
         bonuses: bonuses,
-    }
+    })
 }
 
+/// ポーズの JSON から頂点の位置だけを読む
 pub fn parse_pose_json(data: &str) -> Vec<Point> {
     let pose_json: PoseJSON = serde_json::from_str(&data).expect("failed to parse input as JSON");
+    pose_json_to_vertices(&pose_json)
+}
 
-    let vertices: Vec<Point> = pose_json
+pub fn pose_json_to_vertices(pose_json: &PoseJSON) -> Vec<Point> {
+    pose_json
         .vertices
         .iter()
         .map(|p| Point::new(p[0] as f64, p[1] as f64))
-        .collect();
-
-    vertices
+        .collect()
 }
 
-#[allow(dead_code)]
+/// 問題のファイルを読む
 pub fn load_input(path: &Path) -> Input {
     let file = std::fs::File::open(path).expect(&format!("can't open {}", path.display()));
     let mut buf_reader = std::io::BufReader::new(file);
@@ -100,6 +147,28 @@ pub fn load_input(path: &Path) -> Input {
     parse_input(&data)
 }
 
+pub fn load_pose(path: &Path) -> Vec<Point> {
+    pose_json_to_vertices(&load_pose_json(path))
+}
+
+// ボーナスの情報も含めて読み込む
+pub fn load_pose_json(path: &Path) -> PoseJSON {
+    let data = std::fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("can't load {}: {}", path.display(), e));
+    serde_json::from_str(&data)
+        .unwrap_or_else(|e| panic!("failed to parse {} as JSON: {}", path.display(), e))
+}
+
+// 一時ファイルに書いてから rename するので、途中で止まっても壊れたファイルが残らない
+pub fn write_file_atomically(path: &Path, data: &str) {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = Path::new(&tmp);
+    std::fs::write(tmp, data).unwrap_or_else(|e| panic!("can't write {}: {}", tmp.display(), e));
+    std::fs::rename(tmp, path)
+        .unwrap_or_else(|e| panic!("can't rename {} to {}: {}", tmp.display(), path.display(), e));
+}
+
 pub fn read_input() -> Input {
     let mut data = String::new();
     std::io::stdin().read_to_string(&mut data).unwrap();
@@ -115,6 +184,7 @@ pub fn figure_to_pose_json(
     vertices_to_pose_json(&figure.vertices, using_bonus_types, break_leg)
 }
 
+/// 提出する形のポーズの JSON を作る
 pub fn vertices_to_pose_json(
     vertices: &[Point],
     using_bonus_types: &Vec<BonusType>,
@@ -0,0 +1,57 @@
+//! ICFPC 2021 (Brain Wall) のソルバー。
+//!
+//! 問題の読み書きと検証は [`inout`] と [`common`]、解を作る・改善するソルバーは [`solvers`] にある。
+//! `solve` コマンドが実行するパイプラインは [`pipeline`] から呼び出せるので、
+//! 解析用のツールやテストからもソルバーと全く同じ検証・探索を使える。
+//!
+//! ```
+//! use icfpc2021::common::*;
+//! use icfpc2021::inout::parse_input;
+//!
+//! let input = parse_input(
+//!     r#"{"hole":[[0,0],[20,0],[20,20],[0,20]],"epsilon":0,
+//!         "figure":{"edges":[[0,1]],"vertices":[[0,0],[4,0]]},"bonuses":[]}"#,
+//! );
+//! let pose = vec![Point::new(8.0, 10.0), Point::new(12.0, 10.0)];
+//! assert!(does_valid_pose(&pose, &input.figure, &input.hole, input.epsilon, &vec![], None));
+//! assert_eq!(calculate_dislike(&pose, &input.hole), 656.0);
+//! ```
+
+/// 改善した解をその都度ファイルに書き出す (`ANYTIME_OUTPUT`)
+pub mod anytime;
+/// 保存済みの解の検証 (`audit` コマンド)
+pub mod audit;
+/// 問題セットに対する回帰ベンチマーク (`benchmark` コマンド)
+pub mod benchmark;
+/// 時間・反復回数の予算と、SIGINT/SIGTERM での中断
+pub mod budget;
+/// 幾何の基本的な型と、ポーズの検証・dislike の計算
+pub mod common;
+/// 頂点の固定と領域の制約
+pub mod constraints;
+/// solutions.jsonl との取り込み・書き出し (`import`/`export` コマンド)
+pub mod dump;
+/// ブラウザでポーズを編集するサーバー (`edit` コマンド)
+#[cfg(not(target_arch = "wasm32"))]
+pub mod editor;
+/// 保存済みの解の改善 (`improve` コマンド)
+pub mod improve;
+/// 問題・ポーズ・solutions.jsonl の JSON の読み書き
+pub mod inout;
+/// 外部の MILP ソルバー向けの LP/MPS の書き出しと解の読み込み (`milp-export`/`milp-import` コマンド)
+pub mod milp;
+/// 初期解から改善までのパイプライン (`solve` コマンド)
+pub mod pipeline;
+/// 問題とポーズの SVG/PNG への描画
+pub mod render;
+/// 外部の SAT ソルバー向けの DIMACS CNF の書き出しとモデルの読み込み (`sat-export`/`sat-import` コマンド)
+pub mod sat;
+/// 初期解を作るソルバーと局所探索
+pub mod solvers;
+/// ソルバーの実行結果の統計
+pub mod stats;
+/// 局所探索の途中経過の記録とアニメーション
+pub mod trace;
+/// ブラウザから検証やソルバーを呼ぶための wasm-bindgen の API
+#[cfg(feature = "wasm")]
+pub mod wasm;
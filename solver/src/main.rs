@@ -1,12 +1,487 @@
-mod common;
-mod inout;
-mod solvers;
-
-use crate::common::*;
-use crate::inout::*;
+use icfpc2021::budget::{self, Budget, BudgetPlan};
+use icfpc2021::common::*;
+use icfpc2021::constraints::Constraints;
+use icfpc2021::inout::*;
+use icfpc2021::stats::*;
+use icfpc2021::{
+    anytime, audit, benchmark, dump, editor, improve, milp, pipeline, render, sat, trace,
+};
+use std::path::Path;
 use std::time::Duration;
 
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() >= 2 {
+        match args[1].as_str() {
+            "render" => return render_command(&args[2..]),
+            "animate" => return animate_command(&args[2..]),
+            "edit" => return edit_command(&args[2..]),
+            "improve" => return improve_command(&args[2..]),
+            "benchmark" => return benchmark_command(&args[2..]),
+            "audit" => return audit_command(&args[2..]),
+            "import" => return import_command(&args[2..]),
+            "export" => return export_command(&args[2..]),
+            "milp-export" => return milp_export_command(&args[2..]),
+            "milp-import" => return milp_import_command(&args[2..]),
+            "sat-export" => return sat_export_command(&args[2..]),
+            "sat-import" => return sat_import_command(&args[2..]),
+            _ => {
+                eprintln!("unknown command: {}", args[1]);
+                std::process::exit(2);
+            }
+        }
+    }
+    solve_command();
+}
+
+fn is_image_path(path: &str) -> bool {
+    path.ends_with(".svg") || path.ends_with(".png")
+}
+
+// render PROBLEM [POSE] OUTPUT.svg [OUTPUT.png ...]
+fn render_command(args: &[String]) {
+    if args.len() < 2 {
+        eprintln!("usage: render PROBLEM [POSE] OUTPUT.svg [OUTPUT.png ...]");
+        std::process::exit(2);
+    }
+    let input = load_input(Path::new(&args[0]));
+    let (pose, outputs) = if is_image_path(&args[1]) {
+        (None, &args[1..])
+    } else {
+        (Some(load_pose(Path::new(&args[1]))), &args[2..])
+    };
+    if outputs.is_empty() {
+        eprintln!("no output files are given");
+        std::process::exit(2);
+    }
+    let scene = render::make_scene(&input, pose.as_deref());
+    for output in outputs.iter() {
+        render::save_scene(&scene, Path::new(output));
+    }
+}
+
+// animate PROBLEM TRACE OUTPUT.svg
+// animate PROBLEM TRACE 'frames/{}.png'
+// OUTPUT に {} が含まれていればフレームごとの画像を、そうでなければアニメーション SVG を書き出す
+fn animate_command(args: &[String]) {
+    if args.len() != 3 {
+        eprintln!("usage: animate PROBLEM TRACE OUTPUT.svg|OUTPUT_PATTERN");
+        std::process::exit(2);
+    }
+    let input = load_input(Path::new(&args[0]));
+    let snapshots = trace::load_trace(Path::new(&args[1]));
+    let output = &args[2];
+    if output.contains("{}") {
+        for (i, snapshot) in snapshots.iter().enumerate() {
+            let scene = trace::make_frame(&input, snapshot);
+            let path = output.replace("{}", &format!("{:05}", i));
+            render::save_scene(&scene, Path::new(&path));
+        }
+    } else {
+        let svg = trace::make_animated_svg(&input, &snapshots);
+        std::fs::write(output, svg).unwrap_or_else(|e| panic!("can't write {}: {}", output, e));
+    }
+}
+
+// 実行結果のまとめを stderr に 1 行の JSON で出し、STATS_OUTPUT が指定されていればファイルにも書く
+fn report_summary(summary: &PipelineSummary, stats_output: &Option<String>) {
+    let j = serde_json::to_string(summary).unwrap();
+    eprintln!("summary: {}", j);
+    if let Some(path) = stats_output {
+        std::fs::write(path, j).unwrap_or_else(|e| panic!("can't write {}: {}", path, e));
+    }
+}
+
+// edit PROBLEM [POSE]
+// ポーズを手で編集するためのサーバーを立てる。POSE がなければ元の図形から始める
+fn edit_command(args: &[String]) {
+    if args.is_empty() || args.len() > 2 {
+        eprintln!("usage: edit PROBLEM [POSE]");
+        std::process::exit(2);
+    }
+    let port: u16 = {
+        if let Ok(s) = std::env::var("EDITOR_PORT") {
+            s.parse().expect("Invalid EDITOR_PORT")
+        } else {
+            8080
+        }
+    };
+    let climb_time = {
+        if let Ok(s) = std::env::var("EDITOR_CLIMB_MILLIS") {
+            Duration::from_millis(s.parse().expect("Invalid EDITOR_CLIMB_MILLIS"))
+        } else {
+            Duration::from_millis(200)
+        }
+    };
+    let input = load_input(Path::new(&args[0]));
+    let pose = if args.len() == 2 {
+        load_pose(Path::new(&args[1]))
+    } else {
+        input.figure.vertices.clone()
+    };
+    let session = editor::Session::new(input, pose, climb_time);
+    editor::serve(session, port);
+}
+
+fn time_limit_from_env() -> Duration {
+    if let Ok(s) =
+        std::env::var("TIME_LIMIT_SECONDS").or(std::env::var("HILL_CLIMBING_TIME_LIMIT_SECONDS"))
+    {
+        let f: f64 = s.parse().expect("Invalid TIME_LIMIT_SECONDS");
+        Duration::from_secs_f64(f)
+    } else {
+        Duration::from_millis(2000)
+    }
+}
+
+// FIX_SEED が指定されていれば乱数の系列を固定する。SEED で固定する系列を変えられる
+fn seed_from_env() -> Option<u64> {
+    if std::env::var("FIX_SEED").is_err() {
+        return None;
+    }
+    Some(
+        std::env::var("SEED")
+            .map(|s| s.parse().expect("Invalid SEED"))
+            .unwrap_or(0),
+    )
+}
+
+// 指定されていれば時間の代わりに反復回数で予算を決める
+fn iteration_limit_from_env() -> Option<u64> {
+    std::env::var("ITERATION_LIMIT")
+        .ok()
+        .map(|s| s.parse().expect("Invalid ITERATION_LIMIT"))
+}
+
+fn initial_temperature_from_env() -> f64 {
+    if let Ok(s) = std::env::var("INITIAL_TEMPERATURE") {
+        s.parse::<f64>().expect("Invalid INITIAL_TEMPERATURE")
+    } else {
+        10000.0
+    }
+}
+
+// 焼きなまし系で穴の頂点に図形の頂点を寄せる近傍を使う割合。既定では使わない
+fn corner_move_rate_from_env() -> f64 {
+    if let Ok(s) = std::env::var("CORNER_MOVE_RATE") {
+        s.parse::<f64>().expect("Invalid CORNER_MOVE_RATE")
+    } else {
+        0.0
+    }
+}
+
+// improve PROBLEM SOLUTION
+// improve --all PROBLEMS_DIR SOLUTIONS_DIR
+// 保存済みの解から改善器を走らせ、真に良くなったものだけ書き戻す
+fn improve_command(args: &[String]) {
+    let improvers: Vec<improve::Improver> = {
+        if let Ok(ss) = std::env::var("IMPROVERS") {
+            ss.split(",")
+                .map(|s| s.parse::<improve::Improver>())
+                .collect::<Result<_, _>>()
+                .unwrap_or_else(|e| {
+                    eprintln!("IMPROVERS: {}", e);
+                    std::process::exit(2);
+                })
+        } else {
+            vec![
+                improve::Improver::Orthgonal,
+                improve::Improver::Annealing,
+                improve::Improver::Orthgonal,
+                improve::Improver::Adjust,
+            ]
+        }
+    };
+    budget::install_interrupt_handler();
+    let options = improve::ImproveOptions {
+        improvers,
+        time_limit: time_limit_from_env(),
+        iteration_limit: iteration_limit_from_env(),
+        seed: seed_from_env(),
+        initial_temperature: initial_temperature_from_env(),
+        corner_move_rate: corner_move_rate_from_env(),
+    };
+    if args.len() == 3 && args[0] == "--all" {
+        let problems_dir = Path::new(&args[1]);
+        let mut ids: Vec<u64> = std::fs::read_dir(&args[2])
+            .unwrap_or_else(|e| panic!("can't read {}: {}", args[2], e))
+            .filter_map(|entry| {
+                let path = entry.unwrap().path();
+                if path.extension()? != "solution" {
+                    return None;
+                }
+                path.file_stem()?.to_str()?.parse().ok()
+            })
+            .collect();
+        ids.sort();
+        let mut n_improved = 0;
+        for id in ids.iter() {
+            if budget::is_interrupted() {
+                break;
+            }
+            let problem_path = problems_dir.join(format!("{}.problem", id));
+            if !problem_path.exists() {
+                eprintln!("{} does not exist", problem_path.display());
+                continue;
+            }
+            let solution_path = Path::new(&args[2]).join(format!("{}.solution", id));
+            let result = improve::improve_file(&problem_path, &solution_path, &options);
+            if result == improve::ImproveResult::Improved {
+                n_improved += 1;
+            }
+        }
+        eprintln!("improved {} / {} solutions", n_improved, ids.len());
+    } else if args.len() == 2 {
+        improve::improve_file(Path::new(&args[0]), Path::new(&args[1]), &options);
+    } else {
+        eprintln!("usage: improve PROBLEM SOLUTION");
+        eprintln!("       improve --all PROBLEMS_DIR SOLUTIONS_DIR");
+        std::process::exit(2);
+    }
+}
+
+// benchmark PIPELINE PROBLEMS_DIR IDS [BASELINE]
+// 名前付きのパイプラインを、IDS ("1-10,30" など) の各問題について BENCHMARK_SEEDS の各シード、
+// BENCHMARK_ITERATIONS の各反復回数で実行し、結果の JSON を標準出力に出す。
+// BASELINE (以前の結果の JSON) が指定されていれば比較し、退行があれば終了コード 1 で終わる
+fn benchmark_command(args: &[String]) {
+    if args.len() != 3 && args.len() != 4 {
+        eprintln!("usage: benchmark PIPELINE PROBLEMS_DIR IDS [BASELINE]");
+        std::process::exit(2);
+    }
+    let parse_list = |name: &str, default: &str| -> Vec<u64> {
+        std::env::var(name)
+            .unwrap_or_else(|_| default.to_string())
+            .split(',')
+            .map(|s| {
+                s.trim()
+                    .parse()
+                    .unwrap_or_else(|_| panic!("Invalid {}", name))
+            })
+            .collect()
+    };
+    let seeds = parse_list("BENCHMARK_SEEDS", "0,1,2");
+    let iteration_limits = parse_list("BENCHMARK_ITERATIONS", "100000,1000000");
+    let threshold: f64 = {
+        if let Ok(s) = std::env::var("REGRESSION_THRESHOLD") {
+            s.parse().expect("Invalid REGRESSION_THRESHOLD")
+        } else {
+            0.05
+        }
+    };
+    let pipeline = &args[0];
+    // 名前が間違っていたら実行する前に止める
+    benchmark::pipeline_envs(pipeline);
+    let ids = benchmark::parse_ids(&args[2]);
+    let result = benchmark::run(
+        pipeline,
+        Path::new(&args[1]),
+        &ids,
+        &seeds,
+        &iteration_limits,
+    );
+    println!("{}", serde_json::to_string(&result).unwrap());
+    if args.len() == 4 {
+        let data = std::fs::read_to_string(&args[3])
+            .unwrap_or_else(|e| panic!("can't load {}: {}", args[3], e));
+        let baseline: benchmark::BenchmarkResult =
+            serde_json::from_str(&data).expect("failed to parse baseline as JSON");
+        if baseline.pipeline != result.pipeline {
+            eprintln!(
+                "warning: baseline pipeline is {}, not {}",
+                baseline.pipeline, result.pipeline
+            );
+        }
+        let comparisons = benchmark::compare(&baseline, &result, threshold);
+        benchmark::print_comparisons(&comparisons);
+        let n_regressions = comparisons
+            .iter()
+            .filter(|c| c.verdict == benchmark::Verdict::Regression)
+            .count();
+        if n_regressions > 0 {
+            eprintln!("{} regressions", n_regressions);
+            std::process::exit(1);
+        }
+    }
+}
+
+// audit PROBLEMS_DIR SOLUTIONS_DIR [SOLUTIONS_JSONL]
+// 全ての解をボーナスも含めて検証し、問題のあるものを表にして出す。1 つでもあれば終了コード 1 で終わる。
+// SOLUTIONS_JSONL を省略したときは SOLUTIONS_DIR と同じ場所の solutions.jsonl があればそれと突き合わせる
+fn audit_command(args: &[String]) {
+    if args.len() != 2 && args.len() != 3 {
+        eprintln!("usage: audit PROBLEMS_DIR SOLUTIONS_DIR [SOLUTIONS_JSONL]");
+        std::process::exit(2);
+    }
+    let problems_dir = Path::new(&args[0]);
+    let solutions_dir = Path::new(&args[1]);
+    let recorded_path = if args.len() == 3 {
+        Some(Path::new(&args[2]).to_path_buf())
+    } else {
+        Some(solutions_dir.with_file_name("solutions.jsonl")).filter(|p| p.exists())
+    };
+    let recorded = match &recorded_path {
+        Some(path) => {
+            eprintln!("cross-checking with {}", path.display());
+            load_solution_records(path)
+        }
+        None => vec![],
+    };
+    let entries = audit::audit_all(problems_dir, solutions_dir, &recorded);
+    audit::print_entries(&entries);
+    let n_failures = entries.iter().filter(|e| e.status.is_failure()).count();
+    eprintln!("{} / {} entries have problems", n_failures, entries.len());
+    if n_failures > 0 {
+        std::process::exit(1);
+    }
+}
+
+// import JSONL PROBLEMS_DIR SOLUTIONS_DIR
+// solutions.jsonl の形の記録から、問題ごとに妥当で最良のポーズを SOLUTIONS_DIR に取り込む
+fn import_command(args: &[String]) {
+    if args.len() != 3 {
+        eprintln!("usage: import JSONL PROBLEMS_DIR SOLUTIONS_DIR");
+        std::process::exit(2);
+    }
+    let records = load_solution_records(Path::new(&args[0]));
+    let n_imported = dump::import_records(&records, Path::new(&args[1]), Path::new(&args[2]));
+    eprintln!("imported {} solutions", n_imported);
+}
+
+// export PROBLEMS_DIR SOLUTIONS_DIR
+// SOLUTIONS_DIR の妥当な解を solutions.jsonl と同じ形で標準出力に出す。
+// Commit:Params には COMMIT_PARAMS (省略時は "local:") を入れる
+fn export_command(args: &[String]) {
+    if args.len() != 2 {
+        eprintln!("usage: export PROBLEMS_DIR SOLUTIONS_DIR");
+        std::process::exit(2);
+    }
+    let commit_params = std::env::var("COMMIT_PARAMS").unwrap_or_else(|_| "local:".to_string());
+    let records = dump::export_records(Path::new(&args[0]), Path::new(&args[1]), &commit_params);
+    for record in records.iter() {
+        println!("{}", serde_json::to_string(record).unwrap());
+    }
+}
+
+fn milp_model_from_env(input: &Input) -> milp::AssignmentModel {
+    let max_hole_distance = std::env::var("MILP_MAX_HOLE_DISTANCE")
+        .ok()
+        .map(|s| s.parse().expect("Invalid MILP_MAX_HOLE_DISTANCE"));
+    milp::AssignmentModel::new(input, max_hole_distance)
+}
+
+// milp-export PROBLEM OUTPUT.lp|OUTPUT.mps
+// 頂点と格子点の割り当てのモデルを書き出す。MILP_MAX_HOLE_DISTANCE で穴の頂点から見る距離を打ち切れる
+fn milp_export_command(args: &[String]) {
+    if args.len() != 2 {
+        eprintln!("usage: milp-export PROBLEM OUTPUT.lp|OUTPUT.mps");
+        std::process::exit(2);
+    }
+    let input = load_input(Path::new(&args[0]));
+    let model = milp_model_from_env(&input);
+    let output = &args[1];
+    let data = if output.ends_with(".mps") {
+        model.to_mps()
+    } else if output.ends_with(".lp") {
+        model.to_lp()
+    } else {
+        eprintln!("output must be .lp or .mps: {}", output);
+        std::process::exit(2);
+    };
+    std::fs::write(output, data).unwrap_or_else(|e| panic!("can't write {}: {}", output, e));
+    eprintln!(
+        "{} candidates, {} variables, {} constraints",
+        model.candidates.len(),
+        model.n_variables(),
+        model.n_rows()
+    );
+}
+
+// milp-import PROBLEM SOLUTION
+// 外部のソルバーの解をポーズにして検証し、妥当なら標準出力に出す。
+// MILP_MAX_HOLE_DISTANCE は書き出したときと同じ値にすること
+fn milp_import_command(args: &[String]) {
+    if args.len() != 2 {
+        eprintln!("usage: milp-import PROBLEM SOLUTION");
+        std::process::exit(2);
+    }
+    let input = load_input(Path::new(&args[0]));
+    let model = milp_model_from_env(&input);
+    let solution = std::fs::read_to_string(&args[1])
+        .unwrap_or_else(|e| panic!("can't read {}: {}", args[1], e));
+    let vertices = model.read_solution(&solution).unwrap_or_else(|e| {
+        eprintln!("can't read the solution: {}", e);
+        std::process::exit(1);
+    });
+    print_imported_pose(&input, &vertices);
+}
+
+// 外部のソルバーの解から作ったポーズを検証し、妥当なら標準出力に出す
+fn print_imported_pose(input: &Input, vertices: &[Point]) {
+    let valid = does_valid_pose(
+        &vertices.to_vec(),
+        &input.figure,
+        &input.hole,
+        input.epsilon,
+        &vec![],
+        None,
+    );
+    eprintln!(
+        "dislike = {}, valid = {}",
+        calculate_dislike(vertices, &input.hole),
+        valid
+    );
+    if !valid {
+        std::process::exit(1);
+    }
+    println!("{}", vertices_to_pose_json(vertices, &vec![], &None));
+}
+
+fn sat_encoding_from_env(input: &Input) -> sat::PoseEncoding {
+    let hole_corners = std::env::var("SAT_HOLE_CORNERS").is_ok();
+    sat::PoseEncoding::new(input, hole_corners)
+}
+
+// sat-export PROBLEM OUTPUT.cnf
+// ポーズが存在するかどうかを DIMACS CNF で書き出す。
+// SAT_HOLE_CORNERS が指定されていれば、穴の全ての頂点に図形の頂点を置く (dislike = 0 の) ポーズに限る
+fn sat_export_command(args: &[String]) {
+    if args.len() != 2 {
+        eprintln!("usage: sat-export PROBLEM OUTPUT.cnf");
+        std::process::exit(2);
+    }
+    let input = load_input(Path::new(&args[0]));
+    let encoding = sat_encoding_from_env(&input);
+    std::fs::write(&args[1], encoding.to_dimacs())
+        .unwrap_or_else(|e| panic!("can't write {}: {}", args[1], e));
+    eprintln!(
+        "{} candidates, {} variables, {} clauses",
+        encoding.candidates.len(),
+        encoding.n_variables(),
+        encoding.n_clauses()
+    );
+}
+
+// sat-import PROBLEM MODEL
+// SAT ソルバーのモデルをポーズにして検証し、妥当なら標準出力に出す
+fn sat_import_command(args: &[String]) {
+    if args.len() != 2 {
+        eprintln!("usage: sat-import PROBLEM MODEL");
+        std::process::exit(2);
+    }
+    let input = load_input(Path::new(&args[0]));
+    let encoding = sat_encoding_from_env(&input);
+    let model = std::fs::read_to_string(&args[1])
+        .unwrap_or_else(|e| panic!("can't read {}: {}", args[1], e));
+    let vertices = encoding.read_model(&model).unwrap_or_else(|e| {
+        eprintln!("can't read the model: {}", e);
+        std::process::exit(1);
+    });
+    print_imported_pose(&input, &vertices);
+}
+
+fn solve_command() {
+    budget::install_interrupt_handler();
     let initial_solver: String = {
         if let Ok(s) = std::env::var("INITIAL_SOLVER") {
             s
@@ -28,8 +503,9 @@ fn main() {
             vec![]
         }
     };
-    let fix_seed = std::env::var("FIX_SEED").is_ok();
+    let seed = seed_from_env();
     let disable_dfs_centroid = std::env::var("DISABLE_DFS_CENTROID").is_ok();
+    let disable_dfs_propagation = std::env::var("DISABLE_DFS_PROPAGATION").is_ok();
     let annealing_solver: String = {
         if let Ok(s) = std::env::var("ANNEALING_SOLVER") {
             s
@@ -38,107 +514,105 @@ fn main() {
         }
     };
     let skip_ortho = std::env::var("SKIP_ORTHO").is_ok();
-    let time_limit = {
-        if let Ok(s) = std::env::var("TIME_LIMIT_SECONDS")
-            .or(std::env::var("HILL_CLIMBING_TIME_LIMIT_SECONDS"))
-        {
-            let f: f64 = s.parse().expect("Invalid TIME_LIMIT_SECONDS");
-            Duration::from_secs_f64(f)
+    let render_outputs: Vec<String> = {
+        if let Ok(ss) = std::env::var("RENDER_OUTPUT") {
+            ss.split(",").map(|s| s.to_string()).collect()
         } else {
-            Duration::from_millis(2000)
+            vec![]
         }
     };
-    let initial_temperature = {
-        if let Ok(s) = std::env::var("INITIAL_TEMPERATURE") {
-            s.parse::<f64>().expect("Invalid INITIAL_TEMPERATURE")
+    // TIME_LIMIT_SECONDS (ITERATION_LIMIT) はパイプライン全体の制限時間 (反復回数) で、STAGE_FRACTIONS の重みで各ステージに配分する
+    let time_limit = time_limit_from_env();
+    let iteration_limit = iteration_limit_from_env();
+    let stage_weights = {
+        if let Ok(s) = std::env::var("STAGE_FRACTIONS") {
+            BudgetPlan::parse_weights(&s)
         } else {
-            10000.0
+            BudgetPlan::parse_weights("")
         }
     };
+    let mut tracer: Option<trace::Tracer> = {
+        if let Ok(path) = std::env::var("TRACE_OUTPUT") {
+            let interval = if let Ok(s) = std::env::var("TRACE_INTERVAL") {
+                s.parse::<u64>()
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .expect("Invalid TRACE_INTERVAL")
+            } else {
+                1000
+            };
+            Some(trace::Tracer::create(Path::new(&path), interval))
+        } else {
+            None
+        }
+    };
+    let stats_output = std::env::var("STATS_OUTPUT").ok();
+    let anytime_output = std::env::var("ANYTIME_OUTPUT").ok();
+    let constraints = {
+        if let Ok(s) = std::env::var("CONSTRAINTS") {
+            Constraints::parse(&s)
+        } else if let Ok(path) = std::env::var("CONSTRAINTS_FILE") {
+            Constraints::load(Path::new(&path))
+        } else {
+            Constraints::default()
+        }
+    };
+    let initial_temperature = initial_temperature_from_env();
+    let corner_move_rate = corner_move_rate_from_env();
 
-    eprintln!("time_limit = {:?}", time_limit);
+    let budget = if let Some(n) = iteration_limit {
+        eprintln!("iteration_limit = {}", n);
+        Budget::with_iterations(n)
+    } else {
+        eprintln!("time_limit = {:?}", time_limit);
+        Budget::new(time_limit)
+    };
+    let options = pipeline::PipelineOptions {
+        initial_solver,
+        initial_solution,
+        used_bonus_types,
+        seed,
+        disable_dfs_centroid,
+        disable_dfs_propagation,
+        annealing_solver,
+        skip_ortho,
+        budget,
+        stage_weights,
+        constraints,
+        initial_temperature,
+        corner_move_rate,
+    };
 
     let input = read_input();
+    options
+        .constraints
+        .check_vertex_count(input.figure.vertices.len());
+    if let Some(path) = &anytime_output {
+        anytime::install(
+            Path::new(path),
+            &input,
+            &options.used_bonus_types,
+            &options.constraints,
+        );
+    }
 
-    let initial = if initial_solution.is_none() {
-        eprintln!("initial_solver = {}", initial_solver);
-        match initial_solver.as_str() {
-            "dfs" => solvers::dfs::solve(&input, disable_dfs_centroid),
-            "dfs2" => solvers::dfs2::solve(&input, time_limit),
-            "shrink" => solvers::shrink::solve(&input, fix_seed),
-            _ => panic!("INITIAL_SOLVER {} is invalid.", initial_solver),
-        }
-    } else {
-        let solution = initial_solution.unwrap();
-        eprintln!("using initial solution");
-        let dislike = calculate_dislike(&solution, &input.hole);
-        if !common::does_valid_pose(
-            &solution,
-            &input.figure,
-            &input.hole,
-            input.epsilon,
-            &used_bonus_types,
-            None,
-        ) {
-            panic!("initial solution is invalid pose");
-        };
-        Some((solution, dislike))
-    };
-    if let Some((solution1, dislike1)) = initial {
-        eprintln!("initial: dislike = {}", dislike1);
-
-        let solution2 = if skip_ortho {
-            solution1
-        } else {
-            // orthgonal1
-            let mut input2 = input.clone();
-            input2.figure.vertices = solution1;
-            let (solution2, dislike2) = solvers::orthgonal::solve(&input2).unwrap();
-            eprintln!("orthgonal: dislike = {}", dislike2);
-            solution2
-        };
-
-        eprintln!("annealing_solver = {}", annealing_solver);
-        let (solution3, dislike3) = match annealing_solver.as_str() {
-            "annealing" => solvers::annealing::solve(&input, solution2, time_limit, fix_seed, initial_temperature),
-            "annealing3" => solvers::annealing3::solve(&input, solution2, time_limit, fix_seed, initial_temperature),
-            "hill_climbing" => {
-                solvers::hill_climbing::solve(&input, solution2, time_limit, fix_seed)
-            }
-            _ => panic!("ANNEALING_SOLVER {} is invalid.", annealing_solver),
-        };
-        eprintln!("hill_climbing/annealing: dislike = {}", dislike3);
-
-        let solution4 = if skip_ortho {
-            solution3
-        } else {
-            // orthgonal2
-            let mut input3 = input.clone();
-            input3.figure.vertices = solution3;
-            let (solution4, dislike4) = solvers::orthgonal::solve(&input3).unwrap();
-            eprintln!("orthgonal: dislike = {}", dislike4);
-            solution4
-        };
-
-        // adjust
-        let (solution5, dislike5) = solvers::adjust::solve(&input, &used_bonus_types, solution4);
-        eprintln!("adjust: dislike = {}", dislike5);
-
-        // output
-        let j = vertices_to_pose_json(&solution5, &used_bonus_types, &None);
+    let (pose, summary) = pipeline::run(&input, &options, tracer.as_mut());
+    if let Some(pose) = pose {
+        let j = vertices_to_pose_json(&pose, &options.used_bonus_types, &None);
         println!("{}", j);
-        if !common::does_valid_pose(
-            &solution5,
-            &input.figure,
-            &input.hole,
-            input.epsilon,
-            &used_bonus_types,
-            None,
-        ) {
+        if !render_outputs.is_empty() {
+            let scene = render::make_scene(&input, Some(&pose));
+            for output in render_outputs.iter() {
+                render::save_scene(&scene, Path::new(output));
+            }
+        }
+        report_summary(&summary, &stats_output);
+        if !summary.valid {
             eprintln!("Pose is invalid");
             std::process::exit(1);
         }
     } else {
+        report_summary(&summary, &stats_output);
         eprintln!("No solutions");
         std::process::exit(1);
     }
@@ -0,0 +1,309 @@
+use crate::common::*;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+// 頂点 i を格子点 k に置くかどうかの 0-1 変数 b_i_k による割り当てのモデル (milp/solver.py と同じもの)。
+// LP/MPS 形式で書き出して外部の MILP ソルバーに解かせ、その解をポーズに戻す
+//
+//   assign_i:       sum_k b_i_k = 1
+//   edge_e_v_k:     b_v_k <= sum_{q} b_w_q  (q は辺 e の長さで k から届き、線分 k-q が穴に収まる格子点)
+//   edge_e_w_k:     b_w_k <= sum_{q} b_v_q
+//   near_h:         sum_j l_h_j = 1
+//   reach_h_j:      l_h_j <= sum_{i, k: |h - k|^2 = d_j} b_i_k
+//   minimize        sum_{h, j} d_j * l_h_j
+//
+// l_h_j は穴の頂点 h から最も近い頂点までの距離の 2 乗が d_j であることを表す。
+// max_hole_distance を指定すると、それより遠い距離は l_h_far にまとめてその値で数えるので、目的関数は dislike の下界になる
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Sense {
+    Le,
+    Eq,
+}
+
+#[derive(Debug, Clone)]
+struct Row {
+    name: String,
+    terms: Vec<(usize, f64)>,
+    sense: Sense,
+    rhs: f64,
+}
+
+#[derive(Debug, Clone)]
+struct Variable {
+    name: String,
+    binary: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct AssignmentModel {
+    n_vertices: usize,
+    // 穴の中 (境界を含む) の格子点
+    pub candidates: Vec<Point>,
+    variables: Vec<Variable>,
+    objective: Vec<(usize, f64)>,
+    rows: Vec<Row>,
+}
+
+impl AssignmentModel {
+    pub fn new(input: &Input, max_hole_distance: Option<i64>) -> AssignmentModel {
+        let n = input.figure.vertices.len();
+        let candidates = lattice_points_in_hole(&input.hole);
+        let mut variables = vec![];
+        for i in 0..n {
+            for k in 0..candidates.len() {
+                variables.push(Variable {
+                    name: format!("b_{}_{}", i, k),
+                    binary: true,
+                });
+            }
+        }
+        let b = |i: usize, k: usize| i * candidates.len() + k;
+        let mut rows = vec![];
+
+        for i in 0..n {
+            rows.push(Row {
+                name: format!("assign_{}", i),
+                terms: (0..candidates.len()).map(|k| (b(i, k), 1.0)).collect(),
+                sense: Sense::Eq,
+                rhs: 1.0,
+            });
+        }
+
+        let reachable_by_distance = make_reachable_lattice_points(input, &candidates);
+        for (e, edge) in input.figure.edges.iter().enumerate() {
+            let reachable = &reachable_by_distance[&original_squared_length(input, edge)];
+            for (from, to, label) in [(edge.v, edge.w, "v"), (edge.w, edge.v, "w")].iter() {
+                for (k, qs) in reachable.iter().enumerate() {
+                    let mut terms = vec![(b(*from, k), 1.0)];
+                    terms.extend(qs.iter().map(|&q| (b(*to, q), -1.0)));
+                    rows.push(Row {
+                        name: format!("edge_{}_{}_{}", e, label, k),
+                        terms,
+                        sense: Sense::Le,
+                        rhs: 0.0,
+                    });
+                }
+            }
+        }
+
+        let mut objective = vec![];
+        for (h, hp) in input.hole.exterior().points_iter().skip(1).enumerate() {
+            let mut by_distance: BTreeMap<i64, Vec<usize>> = BTreeMap::new();
+            for (k, c) in candidates.iter().enumerate() {
+                by_distance
+                    .entry(squared_distance(&hp, c) as i64)
+                    .or_default()
+                    .push(k);
+            }
+            let mut near = vec![];
+            for (j, (&d, ks)) in by_distance.iter().enumerate() {
+                if max_hole_distance.is_some_and(|m| d > m) {
+                    break;
+                }
+                let l = variables.len();
+                variables.push(Variable {
+                    name: format!("l_{}_{}", h, j),
+                    binary: false,
+                });
+                objective.push((l, d as f64));
+                near.push((l, 1.0));
+                let mut terms = vec![(l, 1.0)];
+                for i in 0..n {
+                    terms.extend(ks.iter().map(|&k| (b(i, k), -1.0)));
+                }
+                rows.push(Row {
+                    name: format!("reach_{}_{}", h, j),
+                    terms,
+                    sense: Sense::Le,
+                    rhs: 0.0,
+                });
+            }
+            if let Some(m) = max_hole_distance {
+                let l = variables.len();
+                variables.push(Variable {
+                    name: format!("l_{}_far", h),
+                    binary: false,
+                });
+                objective.push((l, m as f64));
+                near.push((l, 1.0));
+            }
+            rows.push(Row {
+                name: format!("near_{}", h),
+                terms: near,
+                sense: Sense::Eq,
+                rhs: 1.0,
+            });
+        }
+
+        AssignmentModel {
+            n_vertices: n,
+            candidates,
+            variables,
+            objective,
+            rows,
+        }
+    }
+
+    pub fn n_variables(&self) -> usize {
+        self.variables.len()
+    }
+
+    pub fn n_rows(&self) -> usize {
+        self.rows.len()
+    }
+
+    // CPLEX LP 形式。1 行が長くなりすぎないように項を折り返す
+    pub fn to_lp(&self) -> String {
+        let mut s = String::new();
+        let write_terms = |s: &mut String, terms: &[(usize, f64)]| {
+            for (t, &(v, c)) in terms.iter().enumerate() {
+                if t > 0 && t % 8 == 0 {
+                    s.push_str("\n   ");
+                }
+                let sign = if c < 0.0 { "-" } else { "+" };
+                write!(s, " {} {} {}", sign, c.abs(), self.variables[v].name).unwrap();
+            }
+        };
+        s.push_str("\\ icfpc2021 vertex/lattice point assignment\nMinimize\n obj:");
+        write_terms(&mut s, &self.objective);
+        s.push_str("\nSubject To\n");
+        for row in self.rows.iter() {
+            write!(s, " {}:", row.name).unwrap();
+            write_terms(&mut s, &row.terms);
+            let sense = match row.sense {
+                Sense::Le => "<=",
+                Sense::Eq => "=",
+            };
+            writeln!(s, " {} {}", sense, row.rhs).unwrap();
+        }
+        s.push_str("Bounds\n");
+        for v in self.variables.iter().filter(|v| !v.binary) {
+            writeln!(s, " 0 <= {} <= 1", v.name).unwrap();
+        }
+        s.push_str("Binary\n");
+        for v in self.variables.iter().filter(|v| v.binary) {
+            writeln!(s, " {}", v.name).unwrap();
+        }
+        s.push_str("End\n");
+        s
+    }
+
+    // 自由形式の MPS。0-1 変数は整数のマーカーで囲み、上限を 1 にする
+    pub fn to_mps(&self) -> String {
+        let mut columns: Vec<Vec<(&str, f64)>> = vec![vec![]; self.variables.len()];
+        for &(v, c) in self.objective.iter() {
+            columns[v].push(("obj", c));
+        }
+        for row in self.rows.iter() {
+            for &(v, c) in row.terms.iter() {
+                columns[v].push((&row.name, c));
+            }
+        }
+        let mut s = String::new();
+        s.push_str("NAME icfpc2021\nROWS\n N obj\n");
+        for row in self.rows.iter() {
+            let sense = match row.sense {
+                Sense::Le => "L",
+                Sense::Eq => "E",
+            };
+            writeln!(s, " {} {}", sense, row.name).unwrap();
+        }
+        s.push_str("COLUMNS\n");
+        let mut in_integer = false;
+        for (v, column) in columns.iter().enumerate() {
+            let binary = self.variables[v].binary;
+            if binary != in_integer {
+                let marker = if binary { "INTORG" } else { "INTEND" };
+                writeln!(s, " MARKER 'MARKER' '{}'", marker).unwrap();
+                in_integer = binary;
+            }
+            for (row, c) in column.iter() {
+                writeln!(s, " {} {} {}", self.variables[v].name, row, c).unwrap();
+            }
+        }
+        if in_integer {
+            s.push_str(" MARKER 'MARKER' 'INTEND'\n");
+        }
+        s.push_str("RHS\n");
+        for row in self.rows.iter().filter(|r| r.rhs != 0.0) {
+            writeln!(s, " rhs {} {}", row.name, row.rhs).unwrap();
+        }
+        s.push_str("BOUNDS\n");
+        for v in self.variables.iter() {
+            writeln!(s, " UP bnd {} 1", v.name).unwrap();
+        }
+        s.push_str("ENDATA\n");
+        s
+    }
+
+    // 外部のソルバーが書き出した解を読み、頂点の位置にする。
+    // ソルバーごとに形式が違うので、変数名の直後に数値が続いている箇所だけを拾う
+    // (SCIP/Gurobi/HiGHS の "名前 値" と CBC の "番号 名前 値 被約費用" のどちらも読める)
+    pub fn read_solution(&self, solution: &str) -> Result<Vec<Point>, String> {
+        let names: HashMap<&str, usize> = self
+            .variables
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| v.binary)
+            .map(|(i, v)| (v.name.as_str(), i))
+            .collect();
+        let mut values = vec![0.0; self.variables.len()];
+        for line in solution.lines() {
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            for t in tokens.windows(2) {
+                if let (Some(&v), Ok(value)) = (names.get(t[0]), t[1].parse::<f64>()) {
+                    values[v] = value;
+                }
+            }
+        }
+        let mut vertices = vec![];
+        for i in 0..self.n_vertices {
+            let placed: Vec<usize> = (0..self.candidates.len())
+                .filter(|&k| values[i * self.candidates.len() + k] > 0.5)
+                .collect();
+            match placed.as_slice() {
+                [k] => vertices.push(self.candidates[*k]),
+                [] => return Err(format!("vertex {} is not placed", i)),
+                _ => return Err(format!("vertex {} is placed {} times", i, placed.len())),
+            }
+        }
+        Ok(vertices)
+    }
+}
+
+#[test]
+fn test_assignment_model() {
+    let input = crate::inout::parse_input(
+        r#"{"hole":[[0,0],[2,0],[2,2],[0,2]],"epsilon":0,"figure":{"edges":[[0,1]],"vertices":[[0,0],[2,0]]},"bonuses":[]}"#,
+    );
+    let model = AssignmentModel::new(&input, None);
+    assert_eq!(model.candidates.len(), 9);
+    // (0,0) から長さ 2 で届くのは (2,0) と (0,2)
+    let k = |x: f64, y: f64| {
+        model
+            .candidates
+            .iter()
+            .position(|p| *p == Point::new(x, y))
+            .unwrap()
+    };
+    let row = model
+        .rows
+        .iter()
+        .find(|r| r.name == format!("edge_0_v_{}", k(0.0, 0.0)))
+        .unwrap();
+    assert_eq!(row.terms.len(), 3);
+    assert!(model.to_lp().contains("Binary\n b_0_0\n"));
+    assert!(model.to_mps().ends_with("ENDATA\n"));
+
+    let solution = format!(
+        "objective value: 4\nb_0_{} 1 (obj:0)\nb_1_{} 1 (obj:0)\nb_0_3 0\n",
+        k(2.0, 0.0),
+        k(2.0, 2.0)
+    );
+    assert_eq!(
+        model.read_solution(&solution),
+        Ok(vec![Point::new(2.0, 0.0), Point::new(2.0, 2.0)])
+    );
+    assert!(model.read_solution("b_0_0 1").is_err());
+}
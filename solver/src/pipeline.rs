@@ -0,0 +1,221 @@
+use crate::anytime;
+use crate::budget::{self, Budget, BudgetPlan};
+use crate::common::*;
+use crate::constraints::Constraints;
+use crate::solvers;
+use crate::stats::*;
+use crate::trace::Tracer;
+use web_time::Instant;
+
+// solve で実行するパイプラインの設定
+#[derive(Debug, Clone)]
+pub struct PipelineOptions {
+    // dfs, dfs2, shrink
+    pub initial_solver: String,
+    // 指定されていれば initial_solver の代わりにこれから始める
+    pub initial_solution: Option<Vec<Point>>,
+    pub used_bonus_types: Vec<BonusType>,
+    pub seed: Option<u64>,
+    pub disable_dfs_centroid: bool,
+    // dfs, dfs2 でドメインの絞り込みをせず、元の探索をする
+    pub disable_dfs_propagation: bool,
+    // annealing, annealing3, hill_climbing, penalty_annealing
+    pub annealing_solver: String,
+    pub skip_ortho: bool,
+    // パイプライン全体の予算。stage_weights の重みで各ステージに配分する
+    pub budget: Budget,
+    pub stage_weights: Vec<(String, f64)>,
+    pub constraints: Constraints,
+    pub initial_temperature: f64,
+    // annealing, annealing3, hill_climbing で穴の頂点に図形の頂点を寄せる近傍を使う割合
+    pub corner_move_rate: f64,
+}
+
+// 初期解を作り、orthgonal, 焼きなまし, orthgonal, adjust の順に改善する。
+// 初期解が得られなければ None を返す
+pub fn run(
+    input: &Input,
+    options: &PipelineOptions,
+    tracer: Option<&mut Tracer>,
+) -> (Option<Vec<Point>>, PipelineSummary) {
+    let start_at = Instant::now();
+    let constraints = &options.constraints;
+    let mut plan = BudgetPlan::new(options.budget.clone(), options.stage_weights.clone());
+    let mut stages: Vec<StageStats> = vec![];
+    let (initial, initial_stats) = if let Some(solution) = &options.initial_solution {
+        eprintln!("using initial solution");
+        let dislike = calculate_dislike(solution, &input.hole);
+        if !does_valid_pose(
+            solution,
+            &input.figure,
+            &input.hole,
+            input.epsilon,
+            &options.used_bonus_types,
+            None,
+        ) {
+            panic!("initial solution is invalid pose");
+        };
+        let stats = StageStats::start("initial_solution");
+        (
+            Some((solution.clone(), dislike)),
+            stats.finish(0, StopReason::Completed, Some(dislike)),
+        )
+    } else {
+        eprintln!("initial_solver = {}", options.initial_solver);
+        match options.initial_solver.as_str() {
+            "dfs" => solvers::dfs::solve(
+                input,
+                options.disable_dfs_centroid,
+                options.disable_dfs_propagation,
+                &plan.stage("initial"),
+                constraints,
+            ),
+            "dfs2" => solvers::dfs2::solve(
+                input,
+                options.disable_dfs_propagation,
+                &plan.stage("initial"),
+                constraints,
+            ),
+            "shrink" => solvers::shrink::solve(input, &plan.stage("initial"), options.seed),
+            _ => panic!("INITIAL_SOLVER {} is invalid.", options.initial_solver),
+        }
+    };
+    stages.push(initial_stats);
+    // shrink や INITIAL_SOLUTION は制約を考慮しないので、ここで確認する
+    let initial = initial.filter(|(solution, _)| {
+        let ok = constraints.is_satisfied(solution);
+        if !ok {
+            eprintln!("initial solution violates constraints");
+        }
+        ok
+    });
+    let (solution1, dislike1) = match initial {
+        Some(initial) => initial,
+        None => {
+            let summary = PipelineSummary {
+                stages,
+                dislike: None,
+                valid: false,
+                interrupted: budget::is_interrupted(),
+                elapsed: start_at.elapsed().as_secs_f64(),
+            };
+            return (None, summary);
+        }
+    };
+    eprintln!("initial: dislike = {}", dislike1);
+    anytime::offer(&solution1);
+
+    let solution2 = if options.skip_ortho {
+        stages.push(StageStats::skipped("orthgonal"));
+        solution1
+    } else {
+        // orthgonal1
+        let mut input2 = input.clone();
+        input2.figure.vertices = solution1;
+        let (ret, stats) = solvers::orthgonal::solve(&input2, &plan.stage("orthgonal1"));
+        stages.push(stats);
+        let (solution2, dislike2) = ret.unwrap();
+        if constraints.is_satisfied(&solution2) {
+            eprintln!("orthgonal: dislike = {}", dislike2);
+            anytime::offer(&solution2);
+            solution2
+        } else {
+            eprintln!("orthgonal: result violates constraints");
+            input2.figure.vertices
+        }
+    };
+
+    eprintln!("annealing_solver = {}", options.annealing_solver);
+    let annealing_budget = plan.stage("annealing");
+    let (solution3, dislike3, annealing_stats) = match options.annealing_solver.as_str() {
+        "annealing" => solvers::annealing::solve(
+            input,
+            solution2,
+            &annealing_budget,
+            options.seed,
+            options.initial_temperature,
+            options.corner_move_rate,
+            constraints,
+            tracer,
+        ),
+        "annealing3" => solvers::annealing3::solve(
+            input,
+            solution2,
+            &annealing_budget,
+            options.seed,
+            options.initial_temperature,
+            options.corner_move_rate,
+            constraints,
+            tracer,
+        ),
+        "penalty_annealing" => solvers::penalty_annealing::solve(
+            input,
+            solution2,
+            &annealing_budget,
+            options.seed,
+            options.initial_temperature,
+            constraints,
+            tracer,
+        ),
+        "hill_climbing" => solvers::hill_climbing::solve(
+            input,
+            solution2,
+            &annealing_budget,
+            options.seed,
+            options.corner_move_rate,
+            constraints,
+            tracer,
+        ),
+        _ => panic!("ANNEALING_SOLVER {} is invalid.", options.annealing_solver),
+    };
+    eprintln!("hill_climbing/annealing: dislike = {}", dislike3);
+    stages.push(annealing_stats);
+
+    let solution4 = if options.skip_ortho {
+        stages.push(StageStats::skipped("orthgonal"));
+        solution3
+    } else {
+        // orthgonal2
+        let mut input3 = input.clone();
+        input3.figure.vertices = solution3;
+        let (ret, stats) = solvers::orthgonal::solve(&input3, &plan.stage("orthgonal2"));
+        stages.push(stats);
+        let (solution4, dislike4) = ret.unwrap();
+        if constraints.is_satisfied(&solution4) {
+            eprintln!("orthgonal: dislike = {}", dislike4);
+            anytime::offer(&solution4);
+            solution4
+        } else {
+            eprintln!("orthgonal: result violates constraints");
+            input3.figure.vertices
+        }
+    };
+
+    // adjust
+    let (solution5, dislike5, adjust_stats) = solvers::adjust::solve(
+        input,
+        &options.used_bonus_types,
+        solution4,
+        &plan.stage("adjust"),
+        constraints,
+    );
+    eprintln!("adjust: dislike = {}", dislike5);
+    stages.push(adjust_stats);
+
+    let valid = does_valid_pose(
+        &solution5,
+        &input.figure,
+        &input.hole,
+        input.epsilon,
+        &options.used_bonus_types,
+        None,
+    );
+    let summary = PipelineSummary {
+        stages,
+        dislike: Some(dislike5),
+        valid,
+        interrupted: budget::is_interrupted(),
+        elapsed: start_at.elapsed().as_secs_f64(),
+    };
+    (Some(solution5), summary)
+}
@@ -0,0 +1,507 @@
+use crate::common::*;
+use std::fmt::Write;
+use std::path::Path;
+
+// 出力画像の長辺のピクセル数
+const IMAGE_SIZE: f64 = 800.0;
+// 問題の外周に付ける余白 (問題の座標系での長さ)
+const MARGIN: f64 = 5.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl Color {
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Color {
+        Color { r, g, b, a: 255 }
+    }
+
+    pub const fn rgba(r: u8, g: u8, b: u8, a: u8) -> Color {
+        Color { r, g, b, a }
+    }
+
+    pub fn to_svg(self) -> String {
+        format!("rgb({},{},{})", self.r, self.g, self.b)
+    }
+
+    pub fn opacity(self) -> f64 {
+        self.a as f64 / 255.0
+    }
+}
+
+const BACKGROUND_COLOR: Color = Color::rgb(0x99, 0x99, 0x99);
+const HOLE_COLOR: Color = Color::rgb(0xe1, 0xdd, 0xd1);
+const HOLE_STROKE_COLOR: Color = Color::rgb(0x55, 0x55, 0x55);
+const ORIGINAL_FIGURE_COLOR: Color = Color::rgba(0x40, 0x40, 0x40, 0x60);
+const VERTEX_COLOR: Color = Color::rgb(0x20, 0x20, 0x20);
+const OUT_OF_HOLE_COLOR: Color = Color::rgb(0xc0, 0x00, 0xc0);
+const TOO_LONG_COLOR: Color = Color::rgb(0xe0, 0x00, 0x00);
+const TOO_SHORT_COLOR: Color = Color::rgb(0x00, 0x40, 0xe0);
+
+#[derive(Debug, Clone)]
+pub enum Shape {
+    Polygon {
+        points: Vec<Point>,
+        fill: Color,
+        stroke: Color,
+        width: f64,
+    },
+    Line {
+        from: Point,
+        to: Point,
+        color: Color,
+        width: f64,
+        dashed: bool,
+    },
+    Circle {
+        center: Point,
+        radius: f64,
+        fill: Color,
+    },
+}
+
+// 問題の座標系で描かれた図形の集まり
+#[derive(Debug, Clone)]
+pub struct Scene {
+    pub min: Point,
+    pub max: Point,
+    pub title: String,
+    pub shapes: Vec<Shape>,
+}
+
+impl Scene {
+    fn scale(&self) -> f64 {
+        let w = self.max.x() - self.min.x();
+        let h = self.max.y() - self.min.y();
+        IMAGE_SIZE / w.max(h).max(1.0)
+    }
+
+    // 出力画像の 1 ピクセルに相当する長さ
+    pub fn unit(&self) -> f64 {
+        1.0 / self.scale()
+    }
+
+    // 点 ps が余白込みで収まるように描画範囲を広げる
+    pub fn include_points(&mut self, ps: &[Point]) {
+        if ps.is_empty() {
+            return;
+        }
+        let (min, max) = calc_bound_box(&ps.to_vec());
+        self.min = Point::new(
+            self.min.x().min(min.x() - MARGIN),
+            self.min.y().min(min.y() - MARGIN),
+        );
+        self.max = Point::new(
+            self.max.x().max(max.x() + MARGIN),
+            self.max.y().max(max.y() + MARGIN),
+        );
+    }
+
+    fn image_size(&self) -> (u32, u32) {
+        let scale = self.scale();
+        let w = ((self.max.x() - self.min.x()) * scale).ceil().max(1.0);
+        let h = ((self.max.y() - self.min.y()) * scale).ceil().max(1.0);
+        (w as u32, h as u32)
+    }
+}
+
+// 辺の伸び縮みの割合を epsilon で正規化した値を返す。
+// [-1, 1] に収まっていれば許容範囲内。
+pub fn normalized_stretch(
+    p1: &Point,
+    p2: &Point,
+    original_p1: &Point,
+    original_p2: &Point,
+    epsilon: i64,
+) -> f64 {
+    let ratio = calc_distance_ratio(p1, p2, original_p1, original_p2);
+    let allowed = epsilon as f64 / 1000000.0;
+    if allowed == 0.0 {
+        if ratio == 0.0 {
+            0.0
+        } else {
+            ratio.signum() * f64::INFINITY
+        }
+    } else {
+        ratio / allowed
+    }
+}
+
+fn lerp_color(c1: Color, c2: Color, t: f64) -> Color {
+    let t = t.clamp(0.0, 1.0);
+    let f = |a: u8, b: u8| (a as f64 + (b as f64 - a as f64) * t).round() as u8;
+    Color::rgba(f(c1.r, c2.r), f(c1.g, c2.g), f(c1.b, c2.b), f(c1.a, c2.a))
+}
+
+// 許容範囲内なら緑 -> 黄 -> 橙、伸びすぎは赤、縮みすぎは青、ホールからはみ出す辺は紫
+pub fn edge_color(stretch: f64, fits_in_hole: bool) -> Color {
+    if !fits_in_hole {
+        return OUT_OF_HOLE_COLOR;
+    }
+    if stretch > 1.0 {
+        return TOO_LONG_COLOR;
+    }
+    if stretch < -1.0 {
+        return TOO_SHORT_COLOR;
+    }
+    let t = stretch.abs();
+    if t < 0.5 {
+        lerp_color(
+            Color::rgb(0x00, 0xa0, 0x30),
+            Color::rgb(0xd0, 0xc0, 0x00),
+            t * 2.0,
+        )
+    } else {
+        lerp_color(
+            Color::rgb(0xd0, 0xc0, 0x00),
+            Color::rgb(0xff, 0x80, 0x00),
+            t * 2.0 - 1.0,
+        )
+    }
+}
+
+fn bonus_color(bonus: &str) -> Color {
+    match bonus {
+        "GLOBALIST" => Color::rgba(0xff, 0xd0, 0x00, 0xb0),
+        "BREAK_A_LEG" => Color::rgba(0x00, 0x60, 0xff, 0xb0),
+        "WALLHACK" => Color::rgba(0xff, 0x60, 0x00, 0xb0),
+        _ => Color::rgba(0x00, 0xc0, 0xc0, 0xb0),
+    }
+}
+
+// ポーズの辺 e の色と、その辺がホールに収まっているかどうかを返す
+pub fn pose_edge_style(input: &Input, pose: &[Point], e: &Edge) -> (Color, bool) {
+    let stretch = normalized_stretch(
+        &pose[e.v],
+        &pose[e.w],
+        &input.figure.vertices[e.v],
+        &input.figure.vertices[e.w],
+        input.epsilon,
+    );
+    let fits = does_line_fit_in_hole(&pose[e.v], &pose[e.w], &input.hole);
+    (edge_color(stretch, fits), fits)
+}
+
+fn hole_points(hole: &Polygon) -> Vec<Point> {
+    hole.exterior().points_iter().skip(1).collect()
+}
+
+// ホール・元の図形・ポーズ・ボーナスの位置を描いたシーンを作る
+pub fn make_scene(input: &Input, pose: Option<&[Point]>) -> Scene {
+    let hole = hole_points(&input.hole);
+    let mut all_points = hole.clone();
+    all_points.extend(input.figure.vertices.iter().copied());
+    if let Some(pose) = pose {
+        all_points.extend(pose.iter().copied());
+    }
+    all_points.extend(input.bonuses.iter().map(|b| b.position));
+    let (min, max) = calc_bound_box(&all_points);
+    let min = Point::new(min.x() - MARGIN, min.y() - MARGIN);
+    let max = Point::new(max.x() + MARGIN, max.y() + MARGIN);
+    let unit = (max.x() - min.x()).max(max.y() - min.y()).max(1.0) / IMAGE_SIZE;
+
+    let mut shapes = vec![];
+    shapes.push(Shape::Polygon {
+        points: hole,
+        fill: HOLE_COLOR,
+        stroke: HOLE_STROKE_COLOR,
+        width: unit * 1.5,
+    });
+    for b in input.bonuses.iter() {
+        shapes.push(Shape::Circle {
+            center: b.position,
+            radius: unit * 8.0,
+            fill: bonus_color(&b.bonus),
+        });
+    }
+    for e in input.figure.edges.iter() {
+        shapes.push(Shape::Line {
+            from: input.figure.vertices[e.v],
+            to: input.figure.vertices[e.w],
+            color: ORIGINAL_FIGURE_COLOR,
+            width: unit * 1.5,
+            dashed: true,
+        });
+    }
+
+    let mut title = format!("epsilon = {}", input.epsilon);
+    if let Some(pose) = pose {
+        for e in input.figure.edges.iter() {
+            let (color, fits) = pose_edge_style(input, pose, e);
+            shapes.push(Shape::Line {
+                from: pose[e.v],
+                to: pose[e.w],
+                color,
+                width: unit * 3.0,
+                dashed: !fits,
+            });
+        }
+        for p in pose.iter() {
+            shapes.push(Shape::Circle {
+                center: *p,
+                radius: unit * 3.0,
+                fill: VERTEX_COLOR,
+            });
+        }
+        title = format!(
+            "{}, dislike = {}",
+            title,
+            calculate_dislike(pose, &input.hole)
+        );
+    }
+
+    Scene {
+        min,
+        max,
+        title,
+        shapes,
+    }
+}
+
+fn points_to_svg(points: &[Point]) -> String {
+    points
+        .iter()
+        .map(|p| format!("{},{}", p.x(), p.y()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// <svg> の開始タグ、<title> と背景を返す
+pub fn svg_header(scene: &Scene) -> String {
+    let (width, height) = scene.image_size();
+    let mut svg = String::new();
+    writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" viewBox="{} {} {} {}">"#,
+        width,
+        height,
+        scene.min.x(),
+        scene.min.y(),
+        scene.max.x() - scene.min.x(),
+        scene.max.y() - scene.min.y()
+    )
+    .unwrap();
+    writeln!(svg, "<title>{}</title>", scene.title).unwrap();
+    writeln!(
+        svg,
+        r#"<rect x="{}" y="{}" width="{}" height="{}" fill="{}"/>"#,
+        scene.min.x(),
+        scene.min.y(),
+        scene.max.x() - scene.min.x(),
+        scene.max.y() - scene.min.y(),
+        BACKGROUND_COLOR.to_svg()
+    )
+    .unwrap();
+    svg
+}
+
+pub fn scene_to_svg(scene: &Scene) -> String {
+    let mut svg = svg_header(scene);
+    for shape in scene.shapes.iter() {
+        writeln!(svg, "{}", shape_to_svg(shape)).unwrap();
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+pub fn shape_to_svg(shape: &Shape) -> String {
+    match shape {
+        Shape::Polygon {
+            points,
+            fill,
+            stroke,
+            width,
+        } => format!(
+            r#"<polygon points="{}" fill="{}" fill-opacity="{}" stroke="{}" stroke-opacity="{}" stroke-width="{}"/>"#,
+            points_to_svg(points),
+            fill.to_svg(),
+            fill.opacity(),
+            stroke.to_svg(),
+            stroke.opacity(),
+            width
+        ),
+        Shape::Line {
+            from,
+            to,
+            color,
+            width,
+            dashed,
+        } => {
+            let dash = if *dashed {
+                format!(r#" stroke-dasharray="{} {}""#, width * 2.0, width * 2.0)
+            } else {
+                String::new()
+            };
+            format!(
+                r#"<line x1="{}" y1="{}" x2="{}" y2="{}" stroke="{}" stroke-opacity="{}" stroke-width="{}" stroke-linecap="round"{}/>"#,
+                from.x(),
+                from.y(),
+                to.x(),
+                to.y(),
+                color.to_svg(),
+                color.opacity(),
+                width,
+                dash
+            )
+        }
+        Shape::Circle {
+            center,
+            radius,
+            fill,
+        } => format!(
+            r#"<circle cx="{}" cy="{}" r="{}" fill="{}" fill-opacity="{}"/>"#,
+            center.x(),
+            center.y(),
+            radius,
+            fill.to_svg(),
+            fill.opacity()
+        ),
+    }
+}
+
+fn to_skia_color(c: Color) -> tiny_skia::Color {
+    tiny_skia::Color::from_rgba8(c.r, c.g, c.b, c.a)
+}
+
+fn skia_paint(c: Color) -> tiny_skia::Paint<'static> {
+    let mut paint = tiny_skia::Paint::default();
+    paint.set_color(to_skia_color(c));
+    paint.anti_alias = true;
+    paint
+}
+
+pub fn scene_to_png(scene: &Scene) -> Vec<u8> {
+    let (width, height) = scene.image_size();
+    let mut pixmap = tiny_skia::Pixmap::new(width, height).expect("invalid image size");
+    pixmap.fill(to_skia_color(BACKGROUND_COLOR));
+    let scale = scene.scale() as f32;
+    let transform =
+        tiny_skia::Transform::from_translate(-scene.min.x() as f32, -scene.min.y() as f32)
+            .post_scale(scale, scale);
+
+    for shape in scene.shapes.iter() {
+        match shape {
+            Shape::Polygon {
+                points,
+                fill,
+                stroke,
+                width,
+            } => {
+                let mut pb = tiny_skia::PathBuilder::new();
+                for (i, p) in points.iter().enumerate() {
+                    if i == 0 {
+                        pb.move_to(p.x() as f32, p.y() as f32);
+                    } else {
+                        pb.line_to(p.x() as f32, p.y() as f32);
+                    }
+                }
+                pb.close();
+                if let Some(path) = pb.finish() {
+                    pixmap.fill_path(
+                        &path,
+                        &skia_paint(*fill),
+                        tiny_skia::FillRule::Winding,
+                        transform,
+                        None,
+                    );
+                    if *width > 0.0 {
+                        let stroke_style = tiny_skia::Stroke {
+                            width: *width as f32,
+                            ..Default::default()
+                        };
+                        pixmap.stroke_path(
+                            &path,
+                            &skia_paint(*stroke),
+                            &stroke_style,
+                            transform,
+                            None,
+                        );
+                    }
+                }
+            }
+            Shape::Line {
+                from,
+                to,
+                color,
+                width,
+                dashed,
+            } => {
+                let mut pb = tiny_skia::PathBuilder::new();
+                pb.move_to(from.x() as f32, from.y() as f32);
+                pb.line_to(to.x() as f32, to.y() as f32);
+                if let Some(path) = pb.finish() {
+                    let w = *width as f32;
+                    let stroke_style = tiny_skia::Stroke {
+                        width: w,
+                        line_cap: tiny_skia::LineCap::Round,
+                        dash: if *dashed {
+                            tiny_skia::StrokeDash::new(vec![w * 2.0, w * 2.0], 0.0)
+                        } else {
+                            None
+                        },
+                        ..Default::default()
+                    };
+                    pixmap.stroke_path(&path, &skia_paint(*color), &stroke_style, transform, None);
+                }
+            }
+            Shape::Circle {
+                center,
+                radius,
+                fill,
+            } => {
+                if let Some(path) = tiny_skia::PathBuilder::from_circle(
+                    center.x() as f32,
+                    center.y() as f32,
+                    *radius as f32,
+                ) {
+                    pixmap.fill_path(
+                        &path,
+                        &skia_paint(*fill),
+                        tiny_skia::FillRule::Winding,
+                        transform,
+                        None,
+                    );
+                }
+            }
+        }
+    }
+    pixmap.encode_png().expect("failed to encode PNG")
+}
+
+// 拡張子が .png なら PNG で、それ以外は SVG で書き出す
+pub fn save_scene(scene: &Scene, path: &Path) {
+    let is_png = path
+        .extension()
+        .map(|e| e.eq_ignore_ascii_case("png"))
+        .unwrap_or(false);
+    let result = if is_png {
+        std::fs::write(path, scene_to_png(scene))
+    } else {
+        std::fs::write(path, scene_to_svg(scene))
+    };
+    result.unwrap_or_else(|e| panic!("can't write {}: {}", path.display(), e));
+}
+
+#[test]
+fn test_edge_color() {
+    assert_eq!(edge_color(0.0, true), Color::rgb(0x00, 0xa0, 0x30));
+    assert_eq!(edge_color(1.5, true), TOO_LONG_COLOR);
+    assert_eq!(edge_color(-1.5, true), TOO_SHORT_COLOR);
+    assert_eq!(edge_color(0.0, false), OUT_OF_HOLE_COLOR);
+}
+
+#[test]
+fn test_normalized_stretch() {
+    let o1 = Point::new(0.0, 0.0);
+    let o2 = Point::new(10.0, 0.0);
+    let p1 = Point::new(0.0, 0.0);
+    let p2 = Point::new(11.0, 0.0);
+    // (121 / 100 - 1) / (210000 / 1000000) = 1.0
+    assert!((normalized_stretch(&p1, &p2, &o1, &o2, 210000) - 1.0).abs() < 1e-9);
+    assert!(normalized_stretch(&p1, &p2, &o1, &o2, 0).is_infinite());
+    assert_eq!(normalized_stretch(&o1, &o2, &o1, &o2, 0), 0.0);
+}
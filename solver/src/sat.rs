@@ -0,0 +1,162 @@
+use crate::common::*;
+use std::fmt::Write;
+
+// ポーズが存在するかどうかを DIMACS CNF にする。dfs が反復回数の上限に達するような
+// epsilon の小さい問題を外部の SAT ソルバーで解くため
+//
+//   x_i_k (= i * K + k + 1): 頂点 i を格子点 candidates[k] に置く
+//   各頂点はちょうど 1 つの格子点に置く (at most one は sequential counter で表す)
+//   辺 (v, w) について x_v_k -> OR_{q} x_w_q (q は k から辺の長さで届き、線分が穴に収まる格子点)。逆向きも同様
+//   hole_corners なら、穴の各頂点にはいずれかの頂点が置かれる (dislike = 0)
+#[derive(Debug, Clone)]
+pub struct PoseEncoding {
+    n_vertices: usize,
+    // 穴の中 (境界を含む) の格子点
+    pub candidates: Vec<Point>,
+    n_variables: usize,
+    clauses: Vec<Vec<i64>>,
+}
+
+impl PoseEncoding {
+    pub fn new(input: &Input, hole_corners: bool) -> PoseEncoding {
+        let n = input.figure.vertices.len();
+        let candidates = lattice_points_in_hole(&input.hole);
+        let k_max = candidates.len();
+        let x = |i: usize, k: usize| (i * k_max + k + 1) as i64;
+        let mut n_variables = n * k_max;
+        let mut clauses = vec![];
+
+        for i in 0..n {
+            clauses.push((0..k_max).map(|k| x(i, k)).collect());
+            // s_k: x_i_0 .. x_i_k のどれかが真
+            let s0 = n_variables as i64 + 1;
+            n_variables += k_max.saturating_sub(1);
+            let s = |k: usize| s0 + k as i64;
+            for k in 0..k_max.saturating_sub(1) {
+                clauses.push(vec![-x(i, k), s(k)]);
+                if k > 0 {
+                    clauses.push(vec![-s(k - 1), s(k)]);
+                }
+                clauses.push(vec![-s(k), -x(i, k + 1)]);
+            }
+        }
+
+        let reachable_by_distance = make_reachable_lattice_points(input, &candidates);
+        for edge in input.figure.edges.iter() {
+            let reachable = &reachable_by_distance[&original_squared_length(input, edge)];
+            for &(from, to) in [(edge.v, edge.w), (edge.w, edge.v)].iter() {
+                for (k, qs) in reachable.iter().enumerate() {
+                    let mut clause = vec![-x(from, k)];
+                    clause.extend(qs.iter().map(|&q| x(to, q)));
+                    clauses.push(clause);
+                }
+            }
+        }
+
+        if hole_corners {
+            for h in input.hole.exterior().points_iter().skip(1) {
+                let k = candidates.iter().position(|c| *c == h).unwrap();
+                clauses.push((0..n).map(|i| x(i, k)).collect());
+            }
+        }
+
+        PoseEncoding {
+            n_vertices: n,
+            candidates,
+            n_variables,
+            clauses,
+        }
+    }
+
+    pub fn n_variables(&self) -> usize {
+        self.n_variables
+    }
+
+    pub fn n_clauses(&self) -> usize {
+        self.clauses.len()
+    }
+
+    pub fn to_dimacs(&self) -> String {
+        let mut s = String::new();
+        writeln!(
+            s,
+            "c icfpc2021 pose: {} vertices x {} lattice points",
+            self.n_vertices,
+            self.candidates.len()
+        )
+        .unwrap();
+        writeln!(s, "p cnf {} {}", self.n_variables, self.clauses.len()).unwrap();
+        for clause in self.clauses.iter() {
+            for lit in clause.iter() {
+                write!(s, "{} ", lit).unwrap();
+            }
+            s.push_str("0\n");
+        }
+        s
+    }
+
+    // SAT ソルバーが書き出したモデルを読み、頂点の位置にする。
+    // minisat の "SAT\n1 -2 ... 0" と、競技会形式の "s SATISFIABLE\nv 1 -2 ... 0" のどちらも読める
+    pub fn read_model(&self, model: &str) -> Result<Vec<Point>, String> {
+        let k_max = self.candidates.len();
+        let mut placed = vec![vec![]; self.n_vertices];
+        for line in model.lines() {
+            let line = line.trim();
+            if line.starts_with("UNSAT") || line.starts_with("s UNSATISFIABLE") {
+                return Err("unsatisfiable".to_string());
+            }
+            if line.starts_with('c') || line.starts_with('s') || line == "SAT" {
+                continue;
+            }
+            for token in line.trim_start_matches('v').split_whitespace() {
+                let lit: i64 = token
+                    .parse()
+                    .map_err(|_| format!("invalid literal {}", token))?;
+                if lit > 0 && (lit as usize) <= self.n_vertices * k_max {
+                    let v = lit as usize - 1;
+                    placed[v / k_max].push(v % k_max);
+                }
+            }
+        }
+        let mut vertices = vec![];
+        for (i, ks) in placed.iter().enumerate() {
+            match ks.as_slice() {
+                [k] => vertices.push(self.candidates[*k]),
+                [] => return Err(format!("vertex {} is not placed", i)),
+                _ => return Err(format!("vertex {} is placed {} times", i, ks.len())),
+            }
+        }
+        Ok(vertices)
+    }
+}
+
+#[test]
+fn test_pose_encoding() {
+    let input = crate::inout::parse_input(
+        r#"{"hole":[[0,0],[2,0],[2,2],[0,2]],"epsilon":0,"figure":{"edges":[[0,1]],"vertices":[[0,0],[2,0]]},"bonuses":[]}"#,
+    );
+    let encoding = PoseEncoding::new(&input, false);
+    assert_eq!(encoding.candidates.len(), 9);
+    // x は 2 * 9 個、sequential counter の補助変数は 2 * 8 個
+    assert_eq!(encoding.n_variables(), 34);
+    assert!(encoding.to_dimacs().contains("p cnf 34 "));
+    let k = |x: f64, y: f64| {
+        encoding
+            .candidates
+            .iter()
+            .position(|p| *p == Point::new(x, y))
+            .unwrap() as i64
+    };
+    // 補助変数の値は読み飛ばす
+    let model = format!(
+        "s SATISFIABLE\nv {} -{} 20 21\nv {} 0\n",
+        k(0.0, 0.0) + 1,
+        k(2.0, 0.0) + 1,
+        9 + k(2.0, 0.0) + 1
+    );
+    assert_eq!(
+        encoding.read_model(&model),
+        Ok(vec![Point::new(0.0, 0.0), Point::new(2.0, 0.0)])
+    );
+    assert!(encoding.read_model("UNSAT\n").is_err());
+}
@@ -1,11 +1,41 @@
+use crate::anytime;
+use crate::budget::Budget;
 use crate::common::*;
+use crate::constraints::Constraints;
+use crate::stats::{StageStats, StopReason};
 
+// 届かない (穴の頂点, 図形の頂点) の組の費用。二乗距離よりずっと大きくする
+const UNREACHABLE: f64 = 1e12;
+
+struct Adjuster<'a> {
+    input: &'a Input,                     // readonly
+    used_bonus_types: &'a Vec<BonusType>, // readonly
+    constraints: &'a Constraints,         // readonly
+    budget: &'a Budget,                   // readonly
+    out_edges: Vec<Vec<usize>>,           // readonly
+    orders: Vec<Vec<usize>>,              // readonly
+    stretch_bounds: Vec<Vec<f64>>,        // readonly
+    // 割当を費用の小さい順に並べた (穴の頂点, 乗せる図形の頂点の候補)。候補の先頭が割り当てた頂点
+    pairs: Vec<(Point, Vec<usize>)>, // readonly
+    max_iter: u64,                   // readonly
+    timed_out: bool,                 // mutable
+    n_iter: u64,                     // mutable
+    best_solution: Vec<Point>,       // mutable
+    best_dislike: f64,               // mutable
+    stats: StageStats,               // mutable
+}
+
+// 図形の頂点を dislike に寄与している穴の頂点に乗せる。
+// 乗っていない穴の頂点と図形の頂点を二乗距離の和が最小になるように割り当て (ハンガリアン法)、
+// 費用の小さい組から乗せて直す。直せなかったら、それより前の組を乗せないことにして戻って探す
 pub fn solve(
     input: &Input,
     used_bonus_types: &Vec<BonusType>,
     solution: Vec<Point>,
-) -> (Vec<Point>, f64) {
-    let mut solution = solution;
+    budget: &Budget,
+    constraints: &Constraints,
+) -> (Vec<Point>, f64, StageStats) {
+    let stats = StageStats::start("adjust");
     let hole_points: Vec<Point> = input.hole.exterior().points_iter().skip(1).collect();
     let n = solution.len();
     let m = hole_points.len();
@@ -24,42 +54,222 @@ pub fn solve(
             }
         }
     }
-    let mut best_dislike = calculate_dislike(&solution, &input.hole);
-    for i in 0..m {
-        if satisfied[i] {
-            continue;
+
+    // 穴の頂点に乗っている頂点から辺を伸ばしきって届く頂点だけを候補にする
+    let stretch_bounds = make_stretch_bounds(input);
+    let corners: Vec<usize> = (0..m).filter(|&j| !satisfied[j]).collect();
+    let vertices: Vec<usize> = (0..n)
+        .filter(|&i| !on_hole_vertex[i] && !constraints.is_fixed(i))
+        .collect();
+    // 列が行より少なければ、届かない列を足して割り当てられるようにする
+    let n_columns = vertices.len().max(corners.len());
+    let cost: Vec<Vec<f64>> = corners
+        .iter()
+        .map(|&j| {
+            let h = &hole_points[j];
+            (0..n_columns)
+                .map(|k| match vertices.get(k) {
+                    Some(&i)
+                        if constraints.allows(i, h)
+                            && is_within_stretch_bounds(
+                                &stretch_bounds,
+                                i,
+                                h,
+                                &solution,
+                                &on_hole_vertex,
+                            ) =>
+                    {
+                        squared_distance(&solution[i], h)
+                    }
+                    _ => UNREACHABLE,
+                })
+                .collect()
+        })
+        .collect();
+    let pairs = make_pairs(&cost, vertices.len())
+        .into_iter()
+        .map(|(r, ks)| (hole_points[corners[r]], ks.into_iter().map(|k| vertices[k]).collect()))
+        .collect();
+
+    let best_dislike = calculate_dislike(&solution, &input.hole);
+    let mut adjuster = Adjuster {
+        input,
+        used_bonus_types,
+        constraints,
+        budget,
+        out_edges,
+        orders,
+        stretch_bounds,
+        pairs,
+        // 戻って探すのは、穴の頂点ごとに全ての図形の頂点を試していた以前の adjust と同じ回数まで
+        max_iter: (m * n) as u64,
+        timed_out: false,
+        n_iter: 0,
+        best_solution: solution.clone(),
+        best_dislike,
+        stats,
+    };
+    adjuster.search(0, &solution, &mut on_hole_vertex);
+
+    let reason = if adjuster.timed_out {
+        budget.stop_reason()
+    } else {
+        StopReason::Completed
+    };
+    let Adjuster {
+        n_iter,
+        best_solution,
+        best_dislike,
+        stats,
+        ..
+    } = adjuster;
+    let stats = stats.finish(n_iter, reason, Some(best_dislike));
+    (best_solution, best_dislike, stats)
+}
+
+// cost の行 (穴の頂点) を割り当ての費用の小さい順に並べ、(行, 乗せる列 (図形の頂点) の候補) を返す。
+// 候補の先頭は割り当てた列で、割り当てた列で直せなかったときのために、届く他の列を近い順に後ろに並べる。
+// 届く列が他の行に割り当てられて届かない列を割り当てられた行も、前の組を乗せないことにしたときのために
+// 最後に回して残す。どの列も届かない行だけ除く。n_vertices より後ろの列は行に揃えるために足した列
+fn make_pairs(cost: &[Vec<f64>], n_vertices: usize) -> Vec<(usize, Vec<usize>)> {
+    let mut pairs: Vec<(f64, usize, Vec<usize>)> = solve_assignment(cost)
+        .into_iter()
+        .enumerate()
+        .filter_map(|(r, k)| {
+            let mut others: Vec<usize> = (0..n_vertices)
+                .filter(|&l| l != k && cost[r][l] < UNREACHABLE)
+                .collect();
+            others.sort_by(|&a, &b| cost[r][a].partial_cmp(&cost[r][b]).unwrap());
+            let assigned = Some(k).filter(|&k| cost[r][k] < UNREACHABLE);
+            let candidates: Vec<usize> = assigned.into_iter().chain(others).collect();
+            if candidates.is_empty() {
+                None
+            } else {
+                Some((cost[r][k], r, candidates))
+            }
+        })
+        .collect();
+    pairs.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+    pairs.into_iter().map(|(_, r, ks)| (r, ks)).collect()
+}
+
+impl Adjuster<'_> {
+    // pairs[k..] を乗せるか乗せないか決める。穴の頂点ごとに、直せて dislike が下がる最初の候補の頂点を乗せる。
+    // anchored (乗せた頂点) は候補にせず、乗せた頂点から辺を伸ばしきって届かない候補も直さずに飛ばす。
+    // 直すときに少しずれるのは dislike が下がるなら許す
+    fn search(&mut self, k: usize, solution: &Vec<Point>, anchored: &mut Vec<bool>) {
+        if k == self.pairs.len()
+            || self.best_dislike == 0.0
+            || self.n_iter >= self.max_iter
+            || self.timed_out
+        {
+            return;
         }
-        for j in 0..n {
-            if on_hole_vertex[j] {
+        let h = self.pairs[k].0;
+        let current_dislike = calculate_dislike(solution, &self.input.hole);
+        for c in 0..self.pairs[k].1.len() {
+            let i = self.pairs[k].1[c];
+            if anchored[i]
+                || !is_within_stretch_bounds(&self.stretch_bounds, i, &h, solution, anchored)
+            {
                 continue;
             }
-            let temp = solution[j];
-            solution[j] = hole_points[i];
-            let next_solution =
-                fix_allowed_distance_violation(j, &solution, &input, &out_edges, &orders);
-            solution[j] = temp;
-            if next_solution.is_none() {
-                continue;
+            if self.n_iter >= self.max_iter {
+                return;
+            }
+            if self.budget.is_over(self.n_iter) {
+                self.timed_out = true;
+                return;
             }
-            let next_solution = next_solution.unwrap();
+            self.n_iter += 1;
 
-            let dislike = calculate_dislike(&next_solution, &input.hole);
-            if does_valid_pose(
-                &next_solution,
-                &input.figure,
-                &input.hole,
-                input.epsilon,
-                used_bonus_types,
+            let mut moved = solution.clone();
+            moved[i] = h;
+            let next_solution = fix_allowed_distance_violation(
+                i,
+                &moved,
+                self.input,
+                &self.out_edges,
+                &self.orders,
                 None,
-            ) && dislike < best_dislike
-            {
-                solution = next_solution;
-                best_dislike = dislike;
-                satisfied[i] = true;
-                on_hole_vertex[j] = true;
+            )
+            .into_valid()
+            .filter(|s| self.is_acceptable(s));
+            if let Some(next_solution) = next_solution {
+                let dislike = calculate_dislike(&next_solution, &self.input.hole);
+                // 乗せても悪くなるなら乗せない
+                if dislike >= current_dislike {
+                    continue;
+                }
+                self.stats.accepted_moves += 1;
+                if dislike < self.best_dislike {
+                    self.best_dislike = dislike;
+                    self.best_solution = next_solution.clone();
+                    self.stats.record_best(self.n_iter, dislike);
+                    anytime::offer(&self.best_solution);
+                }
+                anchored[i] = true;
+                self.search(k + 1, &next_solution, anchored);
+                anchored[i] = false;
                 break;
             }
         }
+        // 乗せない場合
+        self.search(k + 1, solution, anchored);
+    }
+
+    fn is_acceptable(&self, next: &[Point]) -> bool {
+        self.constraints.is_satisfied(next)
+            && does_valid_pose(
+                &next.to_vec(),
+                &self.input.figure,
+                &self.input.hole,
+                self.input.epsilon,
+                self.used_bonus_types,
+                None,
+            )
     }
-    return (solution, best_dislike);
+}
+
+#[test]
+fn test_adjust_snaps_uncovered_corner() {
+    // (0,0) だけ覆われていない。頂点 0 を乗せれば辺の長さも許容範囲に入る
+    let input = crate::inout::parse_input(
+        r#"{"hole":[[0,0],[10,0],[10,10],[0,10]],"epsilon":200000,"figure":{"edges":[[0,1],[1,2],[2,3],[3,0]],"vertices":[[0,0],[10,0],[10,10],[0,10]]},"bonuses":[]}"#,
+    );
+    let pose = vec![
+        Point::new(1.0, 0.0),
+        Point::new(10.0, 0.0),
+        Point::new(10.0, 10.0),
+        Point::new(0.0, 10.0),
+    ];
+    assert_eq!(calculate_dislike(&pose, &input.hole), 1.0);
+    let (solution, dislike, stats) = solve(
+        &input,
+        &vec![],
+        pose,
+        &Budget::with_iterations(1000),
+        &Constraints::default(),
+    );
+    assert_eq!(dislike, 0.0);
+    assert_eq!(solution[0], Point::new(0.0, 0.0));
+    assert_eq!(stats.stop_reason, StopReason::Completed);
+}
+
+#[test]
+fn test_make_pairs() {
+    // 頂点 0 は穴の頂点 0 に割り当てられるが、穴の頂点 1 も頂点 0 を候補に残す。穴の頂点 2 にはどれも届かない
+    let cost = vec![
+        vec![1.0, UNREACHABLE, UNREACHABLE],
+        vec![2.0, UNREACHABLE, UNREACHABLE],
+        vec![UNREACHABLE, UNREACHABLE, UNREACHABLE],
+    ];
+    assert_eq!(make_pairs(&cost, 1), vec![(0, vec![0]), (1, vec![0])]);
+
+    // 割り当ての費用の小さい順に、割り当てた頂点を先頭にして届く他の頂点を近い順に並べる
+    let cost = vec![vec![4.0, 1.0, 9.0], vec![0.5, 2.0, 3.0]];
+    assert_eq!(
+        make_pairs(&cost, 3),
+        vec![(1, vec![0, 1, 2]), (0, vec![1, 0, 2])]
+    );
 }
@@ -1,8 +1,12 @@
+use crate::anytime;
+use crate::budget::Budget;
 use crate::common::*;
+use crate::constraints::Constraints;
+use crate::solvers::moves::{movable_vertices, CornerMoves, VertexMoves};
+use crate::stats::{StageStats, StopReason};
+use crate::trace::Tracer;
 use geo::algorithm::coords_iter::CoordsIter;
 use rand::prelude::*;
-use rand::seq::SliceRandom;
-use std::time::{Duration, Instant};
 
 static SEED: [u8; 32] = [
     0xfd, 0x00, 0xf1, 0x5c, 0xde, 0x01, 0x11, 0xc6, 0xc3, 0xea, 0xfb, 0xbf, 0xf3, 0xca, 0xd8, 0x32,
@@ -33,66 +37,95 @@ fn ascore(solution: &Vec<Point>, input: &Input) -> f64 {
     dislike / (input.hole.exterior().coords_count() as f64) - (vx + vy) * 1.0
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn solve(
     input: &Input,
     mut solution: Vec<Point>,
-    time_limit: Duration,
-    fix_seed: bool,
+    budget: &Budget,
+    seed: Option<u64>,
     initial_temperature: f64,
-) -> (Vec<Point>, f64) {
+    corner_move_rate: f64,
+    constraints: &Constraints,
+    mut tracer: Option<&mut Tracer>,
+) -> (Vec<Point>, f64, StageStats) {
+    let mut stats = StageStats::start("annealing");
     let n = solution.len();
-    let mut rng = if fix_seed {
-        SmallRng::from_seed(SEED)
-    } else {
-        SmallRng::from_entropy()
-    };
+    let mut rng = make_rng(&SEED, seed);
     let mut current_score = ascore(&solution, &input);
-    let out_edges = make_out_edges(&input.figure.edges, n);
-    let original_vertices = &input.figure.vertices;
-    let start_at = Instant::now();
+    let free_vertices = match movable_vertices(constraints, n) {
+        Some(vertices) => vertices,
+        None => {
+            let dislike = calculate_dislike(&solution, &input.hole);
+            let stats = stats.finish(0, StopReason::Completed, Some(dislike));
+            return (solution, dislike, stats);
+        }
+    };
+    let vertex_moves = VertexMoves::new(input, constraints);
+    let corner_moves = CornerMoves::new(input, constraints);
 
     let mut best_solution = solution.clone();
     let mut best_score = current_score;
 
+    let mut progress = 0.0;
     let mut temperature = initial_temperature;
     eprintln!("initial_temperature = {}", initial_temperature);
 
-    let mut iter = 0;
-    let mut move_count = 0;
+    let mut iter: u64 = 0;
     loop {
         // check time limit
         iter += 1;
         if iter % 100 == 0 {
-            let elapsed = Instant::now() - start_at;
-            if best_score == 0.0 || elapsed >= time_limit {
-                eprintln!("iter = {}, move_count = {}", iter, move_count);
+            if best_score == 0.0 || budget.is_over(iter) {
+                let reason = if best_score == 0.0 {
+                    StopReason::OptimalFound
+                } else {
+                    budget.stop_reason()
+                };
                 let dislike = calculate_dislike(&best_solution, &input.hole);
-                return (best_solution, dislike);
+                let stats = stats.finish(iter, reason, Some(dislike));
+                return (best_solution, dislike, stats);
             }
 
             // tweak temperature
-            let progress = elapsed.as_secs_f64() / time_limit.as_secs_f64();
+            progress = budget.progress(iter);
             temperature = initial_temperature * (1.0 - progress) * (-progress).exp2();
         }
 
-        // move to neighbor
-        let i = rng.gen::<usize>() % n;
-        let candidate = make_next_candidates(
-            i,
-            original_vertices,
-            &input.hole,
-            input.epsilon,
-            &solution,
-            &out_edges,
-            &mut rng,
-        );
-        if candidate != original_vertices[i] {
-            move_count += 1;
+        // move to neighbor. corner_move_rate の割合で穴の頂点に図形の頂点を寄せる
+        let mut old_solution = None;
+        let mut old_vertex = None;
+        if corner_move_rate > 0.0 && rng.gen::<f64>() < corner_move_rate {
+            match corner_moves.propose(&solution, &mut rng) {
+                Some(next) => old_solution = Some(std::mem::replace(&mut solution, next)),
+                None => {
+                    if let Some(tracer) = tracer.as_mut() {
+                        tracer.record_move(false);
+                    }
+                    continue;
+                }
+            }
+        } else {
+            let (i, candidate) =
+                match vertex_moves.random_move(&free_vertices, &solution, &mut rng) {
+                    Some(m) => m,
+                    // 穴の頂点に寄せる近傍でまだ動かせるかもしれないので、予算が尽きるまで続ける
+                    None if corner_move_rate > 0.0 => {
+                        if let Some(tracer) = tracer.as_mut() {
+                            tracer.record_move(false);
+                        }
+                        continue;
+                    }
+                    None => {
+                        // どの頂点も動かせない
+                        let dislike = calculate_dislike(&best_solution, &input.hole);
+                        let stats = stats.finish(iter, StopReason::Completed, Some(dislike));
+                        return (best_solution, dislike, stats);
+                    }
+                };
+            old_vertex = Some((i, solution[i]));
+            solution[i] = candidate;
         }
-
         // calculate score. FIXME: slow
-        let old = solution[i];
-        solution[i] = candidate;
         let new_score = ascore(&solution, &input);
 
         let accept = {
@@ -109,90 +142,87 @@ pub fn solve(
         if accept {
             // accept candidate
             current_score = new_score;
+            stats.accepted_moves += 1;
         } else {
             // reject candidate
-            solution[i] = old;
+            if let Some((i, old)) = old_vertex {
+                solution[i] = old;
+            }
+            if let Some(old) = old_solution {
+                solution = old;
+            }
         }
 
         if current_score < best_score {
             best_score = current_score;
             best_solution = solution.clone();
+            stats.record_best(iter, calculate_dislike(&best_solution, &input.hole));
+            anytime::offer(&best_solution);
         }
-    }
-}
 
-fn make_next_candidates(
-    i: usize,
-    original_vertices: &[Point],
-    hole: &Polygon,
-    epsilon: i64,
-    solution: &[Point],
-    out_edges: &[Vec<usize>],
-    rng: &mut SmallRng,
-) -> Point {
-    let some_neighbor = out_edges[i][0];
-    let original_squared_distance =
-        squared_distance(&original_vertices[i], &original_vertices[some_neighbor]);
-    if original_squared_distance < 100.0 || epsilon < 100000 {
-        let ring = Ring::from_epsilon(solution[some_neighbor], epsilon, original_squared_distance);
-
-        let mut points = ring_points(&ring);
-        points.shuffle(rng);
-        for &p in points.iter() {
-            if !is_valid_point_move(i, &p, solution, original_vertices, out_edges, hole, epsilon) {
-                continue;
-            }
-            return p;
-        }
-    } else {
-        let od = original_squared_distance.sqrt();
-        let low = od * (1.0 - epsilon as f64 / 1000000.0).sqrt();
-        let high = od * (1.0 + epsilon as f64 / 1000000.0).sqrt();
-        for _iter in 0..100 {
-            let d = low + (high - low) * rng.gen::<f64>();
-            let theta = 2.0 * std::f64::consts::PI * rng.gen::<f64>();
-            let vect = Point::new(
-                (theta.cos() * d + 0.5).floor(),
-                (theta.sin() * d + 0.5).floor(),
-            );
-            let p = solution[some_neighbor] + vect;
-            if !is_valid_point_move(i, &p, solution, original_vertices, out_edges, hole, epsilon) {
-                continue;
+        if let Some(tracer) = tracer.as_mut() {
+            tracer.record_move(accept);
+            if tracer.should_snapshot(iter) {
+                tracer.snapshot(
+                    "annealing",
+                    iter,
+                    budget.elapsed().as_secs_f64(),
+                    progress,
+                    temperature,
+                    &solution,
+                    &best_solution,
+                    &input.hole,
+                );
             }
-            return p;
         }
-        return solution[i];
     }
-    unreachable!()
 }
 
-fn is_valid_point_move(
-    index: usize,
-    p: &Point,
-    solution: &[Point],
-    original_vertices: &[Point],
-    out_edges: &[Vec<usize>],
-    hole: &Polygon,
-    epsilon: i64,
-) -> bool {
-    let ok1 = out_edges[index].iter().all(|&dst| {
-        is_allowed_distance(
-            &p,
-            &solution[dst],
-            &original_vertices[index],
-            &original_vertices[dst],
-            epsilon,
-            false,
+#[test]
+fn test_iteration_budget_is_deterministic() {
+    // 三角形だとどの頂点も動かせずにすぐ終わるので、折れ線にする
+    let input = crate::inout::parse_input(
+        r#"{"hole":[[0,0],[20,0],[20,20],[0,20]],"epsilon":0,"figure":{"edges":[[0,1],[1,2]],"vertices":[[0,0],[6,0],[0,8]]},"bonuses":[]}"#,
+    );
+    let run = || {
+        let (solution, dislike, stats) = solve(
+            &input,
+            input.figure.vertices.clone(),
+            &Budget::with_iterations(5000),
+            Some(0),
+            100.0,
+            0.0,
+            &Constraints::default(),
+            None,
+        );
+        assert_eq!(stats.iterations, 5000);
+        (solution, dislike)
+    };
+    assert_eq!(run(), run());
+}
+
+#[test]
+fn test_keeps_running_with_corner_moves() {
+    // 穴にぴったり収まった三角形はどの頂点も動かせない
+    let input = crate::inout::parse_input(
+        r#"{"hole":[[0,0],[6,0],[0,8]],"epsilon":0,"figure":{"edges":[[0,1],[1,2],[2,0]],"vertices":[[0,0],[6,0],[0,8]]},"bonuses":[]}"#,
+    );
+    let run = |corner_move_rate: f64| {
+        solve(
+            &input,
+            input.figure.vertices.clone(),
+            &Budget::with_iterations(1000),
+            Some(0),
+            100.0,
+            corner_move_rate,
+            &Constraints::default(),
+            None,
         )
-    });
-    if !ok1 {
-        return false;
-    }
-    let ok2 = out_edges[index]
-        .iter()
-        .all(|&dst| does_line_fit_in_hole(&p, &solution[dst], hole));
-    if !ok2 {
-        return false;
-    }
-    return true;
+        .2
+    };
+    assert_eq!(run(0.0).stop_reason, StopReason::Completed);
+    // 穴の頂点に寄せる近傍を使うなら、予算を使い切るまで続ける
+    let stats = run(0.5);
+    assert_eq!(stats.stop_reason, StopReason::IterationLimit);
+    assert_eq!(stats.iterations, 1000);
 }
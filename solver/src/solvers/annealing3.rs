@@ -1,15 +1,22 @@
+use crate::anytime;
+use crate::budget::Budget;
 use crate::common::*;
+use crate::constraints::Constraints;
+use crate::solvers::moves::{movable_vertices, CornerMoves, VertexMoves};
+use crate::stats::{StageStats, StopReason};
+use crate::trace::Tracer;
 use geo::algorithm::coords_iter::CoordsIter;
 use rand::prelude::*;
-use rand::seq::SliceRandom;
 use std::collections::VecDeque;
-use std::time::{Duration, Instant};
 
 static SEED: [u8; 32] = [
     0xfd, 0x00, 0xf1, 0x5c, 0xde, 0x01, 0x11, 0xc6, 0xc3, 0xea, 0xfb, 0xbf, 0xf3, 0xca, 0xd8, 0x32,
     0x6a, 0xe3, 0x07, 0x99, 0xc5, 0xe0, 0x52, 0xe4, 0xaa, 0x35, 0x07, 0x99, 0xe3, 0x2b, 0x9d, 0xc6,
 ];
 
+// ずれを直すときに頂点を動かして探す回数の上限。1 ステップごとに直すので、違反が減らなくなるまで探すと重すぎる
+const REPAIR_SEARCH_LIMIT: usize = 6;
+
 fn tscore(solution: &Vec<Point>, input: &Input) -> (f64, f64) {
     let dislike = calculate_dislike(&solution, &input.hole);
 
@@ -40,27 +47,39 @@ fn ascore(value: (f64, f64), progress: f64) -> f64 {
     value.0 * progress + (1.0 - progress) * value.1
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn solve(
     input: &Input,
     mut solution: Vec<Point>,
-    time_limit: Duration,
-    fix_seed: bool,
+    budget: &Budget,
+    seed: Option<u64>,
     initial_temperature: f64,
-) -> (Vec<Point>, f64) {
+    corner_move_rate: f64,
+    constraints: &Constraints,
+    mut tracer: Option<&mut Tracer>,
+) -> (Vec<Point>, f64, StageStats) {
+    let mut stats = StageStats::start("annealing3");
     let n = solution.len();
-    let mut rng = if fix_seed {
-        SmallRng::from_seed(SEED)
-    } else {
-        SmallRng::from_entropy()
-    };
+    let mut rng = make_rng(&SEED, seed);
     let mut current_score = tscore(&solution, &input);
     let out_edges = make_out_edges(&input.figure.edges, n);
     let original_vertices = &input.figure.vertices;
+    let free_vertices = match movable_vertices(constraints, n) {
+        Some(vertices) => vertices,
+        None => {
+            let dislike = calculate_dislike(&solution, &input.hole);
+            let stats = stats.finish(0, StopReason::Completed, Some(dislike));
+            return (solution, dislike, stats);
+        }
+    };
+    let vertex_moves = VertexMoves::new(input, constraints);
+    let corner_moves = CornerMoves::new(input, constraints);
+    let pinned: Vec<bool> = (0..n).map(|i| constraints.is_fixed(i)).collect();
+    let stretch_bounds = make_stretch_bounds(input);
     let mut orders = vec![vec![]; n];
     for i in 0..n {
         orders[i] = make_determined_order(&out_edges, Some(i));
     }
-    let start_at = Instant::now();
 
     let mut best_solution = solution.clone();
     let mut best_score = current_score;
@@ -69,50 +88,92 @@ pub fn solve(
     let mut temperature = initial_temperature;
     eprintln!("initial_temperature = {}", initial_temperature);
 
-    let distance_sums = calc_distance_sums(&out_edges, original_vertices.len());
+    let mut distance_sums = calc_distance_sums(&out_edges, original_vertices.len());
+    // 固定された頂点は選ばれないようにする
+    for (i, sum) in distance_sums.iter_mut().enumerate() {
+        if constraints.is_fixed(i) {
+            *sum = 0;
+        }
+    }
     let distance_total: usize = distance_sums.iter().sum();
     // eprintln!("{} {:?}", distance_total, distance_sums);
 
-    let mut iter = 0;
-    let mut move_count = 0;
+    let mut iter: u64 = 0;
     loop {
         // check time limit
         iter += 1;
         if iter % 100 == 0 {
-            let elapsed = Instant::now() - start_at;
-            if best_score.0 == 0.0 || elapsed >= time_limit {
-                eprintln!("iter = {}, move_count = {}", iter, move_count);
+            if best_score.0 == 0.0 || budget.is_over(iter) {
+                let reason = if best_score.0 == 0.0 {
+                    StopReason::OptimalFound
+                } else {
+                    budget.stop_reason()
+                };
                 let dislike = calculate_dislike(&best_solution, &input.hole);
-                return (best_solution, dislike);
+                let stats = stats.finish(iter, reason, Some(dislike));
+                return (best_solution, dislike, stats);
             }
 
             // tweak temperature
-            progress = elapsed.as_secs_f64() / time_limit.as_secs_f64();
+            progress = budget.progress(iter);
             temperature = initial_temperature * (1.0 - progress) * (-progress).exp2();
         }
 
-        // move to neighbor
+        // 移動候補が作れずに continue することがあるので、ループの先頭で記録する
+        if let Some(tracer) = tracer.as_mut() {
+            if tracer.should_snapshot(iter) {
+                tracer.snapshot(
+                    "annealing3",
+                    iter,
+                    budget.elapsed().as_secs_f64(),
+                    progress,
+                    temperature,
+                    &solution,
+                    &best_solution,
+                    &input.hole,
+                );
+            }
+        }
+
+        // move to neighbor. corner_move_rate の割合で穴の頂点に図形の頂点を寄せる
         let r = rng.gen::<f64>();
-        if r > progress {
-            let mut i = 0;
-            {
-                let r = rng.gen::<usize>() % distance_total;
-                let mut sum = 0;
-                for index in 0..n {
-                    sum += distance_sums[index];
-                    if r < sum {
-                        i = index;
-                        break;
+        let corner_move = corner_move_rate > 0.0 && rng.gen::<f64>() < corner_move_rate;
+        if corner_move || r > progress {
+            let next_solution = if corner_move {
+                corner_moves.propose(&solution, &mut rng)
+            } else {
+                let mut i = 0;
+                {
+                    let r = rng.gen::<usize>() % distance_total;
+                    let mut sum = 0;
+                    for index in 0..n {
+                        sum += distance_sums[index];
+                        if r < sum {
+                            i = index;
+                            break;
+                        }
                     }
                 }
-            }
-            let w = rng.gen::<usize>() % 40 + 5;
-            let next_solution =
-                random_move_one_point(i, w, &solution, &input, &mut rng, &out_edges, &orders);
+                let w = rng.gen::<usize>() % 40 + 5;
+                random_move_one_point(
+                    i,
+                    w,
+                    &solution,
+                    &input,
+                    &mut rng,
+                    &out_edges,
+                    &orders,
+                    &stretch_bounds,
+                    &pinned,
+                )
+                .filter(|s| constraints.is_satisfied(s))
+            };
             if next_solution.is_none() {
+                if let Some(tracer) = tracer.as_mut() {
+                    tracer.record_move(false);
+                }
                 continue;
             }
-            move_count += 1;
             let next_solution = next_solution.unwrap();
 
             // calculate score. FIXME: slow
@@ -135,22 +196,23 @@ pub fn solve(
                 // accept candidate
                 current_score = new_score;
                 solution = next_solution;
+                stats.accepted_moves += 1;
             }
-        } else {
-            let i = rng.gen::<usize>() % n;
-            let candidate = make_next_candidates(
-                i,
-                original_vertices,
-                &input.hole,
-                input.epsilon,
-                &solution,
-                &out_edges,
-                &mut rng,
-            );
-            if candidate != original_vertices[i] {
-                move_count += 1;
+            if let Some(tracer) = tracer.as_mut() {
+                tracer.record_move(accept);
             }
-
+        } else {
+            // 1 点だけ動かせる頂点が無ければ、もう一方の近傍に任せる
+            let (i, candidate) =
+                match vertex_moves.random_move(&free_vertices, &solution, &mut rng) {
+                    Some(m) => m,
+                    None => {
+                        if let Some(tracer) = tracer.as_mut() {
+                            tracer.record_move(false);
+                        }
+                        continue;
+                    }
+                };
             // calculate score. FIXME: slow
             let old = solution[i];
             solution[i] = candidate;
@@ -172,95 +234,26 @@ pub fn solve(
             if accept {
                 // accept candidate
                 current_score = new_score;
+                stats.accepted_moves += 1;
             } else {
                 // reject candidate
                 solution[i] = old;
             }
+            if let Some(tracer) = tracer.as_mut() {
+                tracer.record_move(accept);
+            }
         }
 
         if current_score < best_score {
             best_score = current_score;
             best_solution = solution.clone();
+            stats.record_best(iter, calculate_dislike(&best_solution, &input.hole));
+            anytime::offer(&best_solution);
         }
     }
 }
 
-fn make_next_candidates(
-    i: usize,
-    original_vertices: &[Point],
-    hole: &Polygon,
-    epsilon: i64,
-    solution: &[Point],
-    out_edges: &[Vec<usize>],
-    rng: &mut SmallRng,
-) -> Point {
-    let some_neighbor = out_edges[i][0];
-    let original_squared_distance =
-        squared_distance(&original_vertices[i], &original_vertices[some_neighbor]);
-    if original_squared_distance < 100.0 || epsilon < 100000 {
-        let ring = Ring::from_epsilon(solution[some_neighbor], epsilon, original_squared_distance);
-
-        let mut points = ring_points(&ring);
-        points.shuffle(rng);
-        for &p in points.iter() {
-            if !is_valid_point_move(i, &p, solution, original_vertices, out_edges, hole, epsilon) {
-                continue;
-            }
-            return p;
-        }
-    } else {
-        let od = original_squared_distance.sqrt();
-        let low = od * (1.0 - epsilon as f64 / 1000000.0).sqrt();
-        let high = od * (1.0 + epsilon as f64 / 1000000.0).sqrt();
-        for _iter in 0..100 {
-            let d = low + (high - low) * rng.gen::<f64>();
-            let theta = 2.0 * std::f64::consts::PI * rng.gen::<f64>();
-            let vect = Point::new(
-                (theta.cos() * d + 0.5).floor(),
-                (theta.sin() * d + 0.5).floor(),
-            );
-            let p = solution[some_neighbor] + vect;
-            if !is_valid_point_move(i, &p, solution, original_vertices, out_edges, hole, epsilon) {
-                continue;
-            }
-            return p;
-        }
-        return solution[i];
-    }
-    unreachable!()
-}
-
-fn is_valid_point_move(
-    index: usize,
-    p: &Point,
-    solution: &[Point],
-    original_vertices: &[Point],
-    out_edges: &[Vec<usize>],
-    hole: &Polygon,
-    epsilon: i64,
-) -> bool {
-    let ok1 = out_edges[index].iter().all(|&dst| {
-        is_allowed_distance(
-            &p,
-            &solution[dst],
-            &original_vertices[index],
-            &original_vertices[dst],
-            epsilon,
-            false,
-        )
-    });
-    if !ok1 {
-        return false;
-    }
-    let ok2 = out_edges[index]
-        .iter()
-        .all(|&dst| does_line_fit_in_hole(&p, &solution[dst], hole));
-    if !ok2 {
-        return false;
-    }
-    return true;
-}
-
+#[allow(clippy::too_many_arguments)]
 fn random_move_one_point(
     from: usize,
     w: usize,
@@ -269,6 +262,8 @@ fn random_move_one_point(
     rng: &mut SmallRng,
     out_edges: &Vec<Vec<usize>>,
     orders: &Vec<Vec<usize>>,
+    stretch_bounds: &[Vec<f64>],
+    pinned: &[bool],
 ) -> Option<Vec<Point>> {
     let mut gx: f64 = 0.0;
     let mut gy: f64 = 0.0;
@@ -295,12 +290,23 @@ fn random_move_one_point(
     if solution[from] == np {
         return None;
     }
+    // 固定された頂点から届かない位置なら、ずれを直すまでもなく失敗する
+    if !is_within_stretch_bounds(stretch_bounds, from, &np, solution, pinned) {
+        return None;
+    }
 
     let mut solution = solution.clone();
     let old = solution[from];
     solution[from] = np;
-    let next_solution =
-        fix_allowed_distance_violation(from, &solution, &input, &out_edges, &orders);
+    let next_solution = fix_allowed_distance_violation(
+        from,
+        &solution,
+        &input,
+        &out_edges,
+        &orders,
+        Some(REPAIR_SEARCH_LIMIT),
+    )
+    .into_valid();
     solution[from] = old;
     return next_solution;
 }
@@ -1,4 +1,8 @@
+use crate::budget::Budget;
 use crate::common::*;
+use crate::constraints::Constraints;
+use crate::solvers::domain::LatticeDomains;
+use crate::stats::{StageStats, StopReason};
 use geo::algorithm::centroid::Centroid;
 use geo::algorithm::contains::Contains;
 // use rand::rngs::SmallRng;
@@ -10,37 +14,71 @@ use geo::algorithm::contains::Contains;
 //     0x6a, 0xe3, 0x07, 0x99, 0xc5, 0xe0, 0x52, 0xe4, 0xaa, 0x35, 0x07, 0x99, 0xe3, 0x2b, 0x9d, 0xc6,
 // ];
 
-struct Solver {
+struct Solver<'a> {
     original_vertices: Vec<Point>, // readonly
     out_edges: Vec<Vec<usize>>,    // readonly
     epsilon: i64,                  // readonly
     hole: Polygon,                 // readonly
     holl_points: Vec<Point>,       // readonly
+    stretch_bounds: Vec<Vec<f64>>, // readonly
+    constraints: &'a Constraints,  // readonly
+    budget: &'a Budget,            // readonly
+    timed_out: bool,               // mutable
+    n_iter: u64,                   // mutable
+    n_placed: u64,                 // mutable
                                    // rng: SmallRng,                 // mutable
 }
 
-pub fn solve(input: &Input, disable_dfs_centroid: bool) -> Option<(Vec<Point>, f64)> {
+pub fn solve(
+    input: &Input,
+    disable_dfs_centroid: bool,
+    disable_dfs_propagation: bool,
+    budget: &Budget,
+    constraints: &Constraints,
+) -> (Option<(Vec<Point>, f64)>, StageStats) {
+    let mut stats = StageStats::start("dfs");
     let mut solver = Solver {
         original_vertices: input.figure.vertices.clone(),
         out_edges: make_out_edges(&input.figure.edges, input.figure.vertices.len()),
         epsilon: input.epsilon,
         hole: input.hole.clone(),
         holl_points: all_point_in_hole(&input.hole, disable_dfs_centroid),
+        stretch_bounds: make_stretch_bounds(input),
+        constraints,
+        budget,
+        timed_out: false,
+        n_iter: 0,
+        n_placed: 0,
         // rng: SmallRng::from_seed(SEED),
     };
     let mut vertices = input.figure.vertices.clone();
     let mut visited = vec![false; input.figure.vertices.len()];
-    let order = make_determined_order(&solver.out_edges, None);
-
-    if solver.naive_dfs(0, &mut vertices, &mut visited, &order) {
+    let found = if disable_dfs_propagation {
+        // 固定された頂点があればそこから置いていく
+        let order = make_determined_order(&solver.out_edges, constraints.first_fixed_vertex());
+        solver.naive_dfs(0, &mut vertices, &mut visited, &order)
+    } else {
+        // 固定された頂点はドメインが 1 点なので、MRV で最初に選ばれる
+        let mut domains = LatticeDomains::new(input, solver.holl_points.clone(), constraints);
+        solver.propagating_dfs(0, &mut vertices, &mut visited, &mut domains)
+    };
+    stats.accepted_moves = solver.n_placed;
+    if found {
         let dislike = calculate_dislike(&vertices, &input.hole);
-        Some((vertices, dislike))
+        let stats = stats.finish(solver.n_iter, StopReason::Completed, Some(dislike));
+        (Some((vertices, dislike)), stats)
     } else {
-        None
+        let reason = if solver.timed_out {
+            budget.stop_reason()
+        } else {
+            StopReason::NotFound
+        };
+        let stats = stats.finish(solver.n_iter, reason, None);
+        (None, stats)
     }
 }
 
-impl Solver {
+impl Solver<'_> {
     /*
     fn dfs(&self, i: usize, vertices: &mut [Point], visited: &mut [bool]) {
         for &j in self.out_edges[i].iter() {
@@ -73,12 +111,28 @@ impl Solver {
         if i == self.original_vertices.len() {
             return true;
         }
+        self.n_iter += 1;
+        // タイムリミット
+        if self.n_iter % 10000 == 0 && self.budget.is_over(self.n_iter) {
+            self.timed_out = true;
+        }
+        if self.timed_out {
+            return false;
+        }
         let src = order[i];
         visited[src] = true;
 
-        let holl_points = self.holl_points.clone();
+        let holl_points = match self.constraints.fixed_point(src) {
+            Some(p) => vec![p],
+            None => self.holl_points.clone(),
+        };
         //holl_points.shuffle(&mut self.rng);
         for &p in holl_points.iter() {
+            if !self.constraints.allows(src, &p)
+                || !is_within_stretch_bounds(&self.stretch_bounds, src, &p, vertices, visited)
+            {
+                continue;
+            }
             vertices[src] = p;
 
             // verify
@@ -95,6 +149,7 @@ impl Solver {
             });
 
             if ok {
+                self.n_placed += 1;
                 if self.naive_dfs(i + 1, vertices, visited, order) {
                     visited[src] = false;
                     return true;
@@ -105,6 +160,53 @@ impl Solver {
         visited[src] = false;
         false
     }
+
+    // naive_dfs と違い、置いた頂点の隣のドメインを絞り込み、ドメインが最小の頂点から置いていく。
+    // ドメインに残っている点は置き済みの隣の頂点との辺の長さを満たすので、線分だけ確かめる
+    fn propagating_dfs(
+        &mut self,
+        i: usize,
+        vertices: &mut [Point],
+        placed: &mut [bool],
+        domains: &mut LatticeDomains,
+    ) -> bool {
+        if i == self.original_vertices.len() {
+            return true;
+        }
+        self.n_iter += 1;
+        // タイムリミット。1 回の絞り込みが重いので毎回確かめる
+        if self.budget.is_over(self.n_iter) {
+            self.timed_out = true;
+        }
+        if self.timed_out {
+            return false;
+        }
+        let src = domains.select_unplaced(placed).unwrap();
+        placed[src] = true;
+        for k in domains.members(src) {
+            let p = domains.candidate(k);
+            if !is_within_stretch_bounds(&self.stretch_bounds, src, &p, vertices, placed)
+                || !domains.fits_placed_neighbors(src, &p, vertices, placed)
+            {
+                continue;
+            }
+            vertices[src] = p;
+            let mark = domains.mark();
+            if domains.place(src, &p, placed) {
+                self.n_placed += 1;
+                if self.propagating_dfs(i + 1, vertices, placed, domains) {
+                    placed[src] = false;
+                    return true;
+                }
+            }
+            domains.undo(mark);
+            if self.timed_out {
+                break;
+            }
+        }
+        placed[src] = false;
+        false
+    }
 }
 
 fn each_point_in_hole(hole: &Polygon, mut f: impl FnMut(Point)) {
@@ -1,19 +1,32 @@
+use crate::anytime;
+use crate::budget::Budget;
 use crate::common::*;
+use crate::constraints::Constraints;
+use crate::solvers::domain::LatticeDomains;
+use crate::stats::{StageStats, StopReason};
 use geo::algorithm::contains::Contains;
 use rand::prelude::*;
 use rand::seq::SliceRandom;
+use std::cell::Cell;
 use std::collections::HashSet;
 use std::iter::FromIterator;
-use std::time::{Duration, Instant};
 
 type Vector2d = geo::Coordinate<f64>;
 
+// 解が見つかった後、1 つの初期点で探索する反復回数は最初の解にかかった回数のこの倍まで
+const FOUND_ITER_FACTOR: i64 = 4;
+
 static SEED: [u8; 32] = [
     0xfd, 0x00, 0xf1, 0x5c, 0xde, 0x01, 0x11, 0xc6, 0xc3, 0xea, 0xfb, 0xbf, 0xf3, 0xca, 0xd8, 0x32,
     0x6a, 0xe3, 0x07, 0x99, 0xc5, 0xe0, 0x52, 0xe4, 0xaa, 0x35, 0x07, 0x99, 0xe3, 0x2b, 0x9d, 0xc6,
 ];
 
-pub fn solve(input: &Input, time_limit: Duration) -> Option<(Vec<Point>, f64)> {
+pub fn solve(
+    input: &Input,
+    disable_dfs_propagation: bool,
+    budget: &Budget,
+    constraints: &Constraints,
+) -> (Option<(Vec<Point>, f64)>, StageStats) {
     let n = input.figure.vertices.len();
 
     //let original_vertices = input.figure.vertices.clone();
@@ -42,8 +55,12 @@ pub fn solve(input: &Input, time_limit: Duration) -> Option<(Vec<Point>, f64)> {
         original: input.figure.vertices.clone(),
         hole: input.hole.clone(),
         boundary_terminals: HashSet::from_iter(input.hole.exterior().points_iter().map(|p| (p.x() as i64, p.y() as i64))),
-        time_limit: time_limit,
-        start_at: Instant::now(),
+        budget: budget.clone(),
+        iter_base: Cell::new(0),
+        best_dislike: Cell::new(1e20),
+        first_found_iter: Cell::new(0),
+        stretch_bounds: make_stretch_bounds(input),
+        constraints,
     };
 
     let order = solver.reorder();
@@ -54,7 +71,19 @@ pub fn solve(input: &Input, time_limit: Duration) -> Option<(Vec<Point>, f64)> {
 
     //eprintln!("possible_ranges = {:?}", possible_ranges);
 
-    solver.search(&order, &possible_ranges)
+    // 置いた頂点の隣のドメインを絞り込んで行き詰まりを早く見つけ、次はドメインが最小の頂点を置く。
+    // 無効にしたときは辺の順番に置いていく
+    let mut domains = if disable_dfs_propagation {
+        None
+    } else {
+        Some(LatticeDomains::new(
+            input,
+            lattice_points_in_hole(&input.hole),
+            constraints,
+        ))
+    };
+
+    solver.search(&order, &possible_ranges, &mut domains)
 }
 
 /*
@@ -74,7 +103,7 @@ struct PossibleRange {
     free: bool,
 }
 
-struct Solver {
+struct Solver<'a> {
     vertex_count: usize,
     edge_count: usize,
     out_edges: Vec<Vec<usize>>,
@@ -86,11 +115,19 @@ struct Solver {
     original: Vec<Point>,
     hole: Polygon,
     boundary_terminals: HashSet<(i64, i64)>,
-    time_limit: Duration,
-    start_at: Instant,
+    budget: Budget,
+    // 今の初期点より前の初期点で数えた反復回数の合計
+    iter_base: Cell<u64>,
+    // 今までの初期点で見つけた最良の dislike。これより良くならない枝は探さない
+    best_dislike: Cell<f64>,
+    // 最初の解を見つけた初期点での反復回数 (100 以上にする)
+    first_found_iter: Cell<i64>,
+    // 頂点の組ごとの距離の上限
+    stretch_bounds: Vec<Vec<f64>>,
+    constraints: &'a Constraints,
 }
 
-impl Solver {
+impl Solver<'_> {
     // まず、edge を見ていく順番を求める
     fn reorder(&self) -> Vec<Edge> {
         let mut tecomp_visited = vec![false; self.tecomp.len()];
@@ -107,6 +144,10 @@ impl Solver {
                 best_oe = oe;
             }
         }
+        // 固定された頂点があればそこから探索を始める
+        if let Some(v) = self.constraints.first_fixed_vertex() {
+            start_vertex = v;
+        }
 
         let tecomp_id = self.vertex2tecomp[start_vertex];
         self.reorder_tecomps(
@@ -204,17 +245,25 @@ impl Solver {
     }
 
     fn search(
-        &self, order: &[Edge], possible_ranges: &[PossibleRange]
-    ) -> Option<(Vec<Point>, f64)> {
+        &self, order: &[Edge], possible_ranges: &[PossibleRange], domains: &mut Option<LatticeDomains>,
+    ) -> (Option<(Vec<Point>, f64)>, StageStats) {
+        let mut stats = StageStats::start("dfs2");
+        let mut total_iter: u64 = 0;
         let mut rng = SmallRng::from_seed(SEED);
-        let mut candidates: Vec<Point> = self.hole.exterior().points_iter().collect();
-
-        let mut hole_points = all_points_in_hole(&self.hole);
-        hole_points.shuffle(&mut rng);
-        candidates.extend(hole_points.iter().take(20));
+        let start_vertex = order[0].v;
+        let candidates: Vec<Point> = if let Some(p) = self.constraints.fixed_point(start_vertex) {
+            vec![p]
+        } else {
+            let mut candidates: Vec<Point> = self.hole.exterior().points_iter().collect();
+            candidates.retain(|p| self.constraints.allows(start_vertex, p));
+            let mut hole_points = all_points_in_hole(&self.hole);
+            hole_points.retain(|p| self.constraints.allows(start_vertex, p));
+            hole_points.shuffle(&mut rng);
+            candidates.extend(hole_points.iter().take(20));
+            candidates
+        };
 
         let mut best_solution = None;
-        let mut best_dislike = 1e20;
 
         for &pos in candidates.iter() {
             let mut solution = self.original.clone();
@@ -225,24 +274,81 @@ impl Solver {
             determined[v] = true;
 
             let mut n_iter = 0;
+            self.iter_base.set(total_iter);
 
-            if let Some((s, dislike)) = self.dfs(0, order, possible_ranges, &mut solution, &mut determined, &mut n_iter) {
-                if dislike < best_dislike {
+            // この初期点からは今より良い解が見つからない
+            if self.cannot_improve(&solution, &determined) {
+                continue;
+            }
+
+            let found = match domains {
+                Some(d) => {
+                    let mark = d.mark();
+                    let found = if d.contains(v, &pos) && d.place(v, &pos, &determined) {
+                        self.propagating_dfs(1, &mut solution, &mut determined, &mut n_iter, d)
+                    } else {
+                        None
+                    };
+                    d.undo(mark);
+                    found
+                }
+                None => self.dfs(0, order, possible_ranges, &mut solution, &mut determined, &mut n_iter),
+            };
+            total_iter += n_iter as u64;
+            if let Some((s, dislike)) = found {
+                if best_solution.is_none() {
+                    self.first_found_iter.set(n_iter.max(100));
+                }
+                stats.accepted_moves += 1;
+                if dislike < self.best_dislike.get() {
+                    anytime::offer(&s);
                     best_solution = Some(s);
-                    best_dislike = dislike;
+                    self.best_dislike.set(dislike);
+                    stats.record_best(total_iter, dislike);
                 }
-            } else {
-                eprintln!("not found... (n_iter = {}, start = {:?})", n_iter, pos);
             }
 
             // タイムリミットを超えていたらすぐに終了する
-            if Instant::now() - self.start_at >= self.time_limit {
-                eprintln!("time limit exceeded. return early.");
-                return best_solution.map(|s| (s, best_dislike));
+            if self.budget.is_over(total_iter) {
+                return self.finish_search(best_solution, stats, total_iter, self.budget.stop_reason());
             }
         }
 
-        best_solution.map(|s| (s, best_dislike))
+        self.finish_search(best_solution, stats, total_iter, StopReason::Completed)
+    }
+
+    // 1 つの初期点で探索する反復回数の上限。解が見つかった後は下界で枝刈りするので、
+    // 上限が大きいと 1 つの初期点で探索し続けてしまう (problem 88 で悪くなる)
+    fn max_iter(&self) -> i64 {
+        if self.best_dislike.get() < 1e20 {
+            self.first_found_iter.get() * FOUND_ITER_FACTOR
+        } else {
+            10000000
+        }
+    }
+
+    // 置き済みの頂点から求めた dislike の下界が、今までの最良解に届かないか。
+    // 最初の解が見つかるまでは下界を計算しない
+    fn cannot_improve(&self, solution: &[Point], determined: &[bool]) -> bool {
+        let best_dislike = self.best_dislike.get();
+        best_dislike < 1e20
+            && calc_dislike_lower_bound(solution, determined, &self.stretch_bounds, &self.hole) >= best_dislike
+    }
+
+    fn finish_search(
+        &self,
+        best_solution: Option<Vec<Point>>,
+        stats: StageStats,
+        total_iter: u64,
+        reason: StopReason,
+    ) -> (Option<(Vec<Point>, f64)>, StageStats) {
+        match best_solution {
+            Some(s) => {
+                let best_dislike = self.best_dislike.get();
+                (Some((s, best_dislike)), stats.finish(total_iter, reason, Some(best_dislike)))
+            }
+            None => (None, stats.finish(total_iter, StopReason::NotFound, None)),
+        }
     }
 
     fn dfs(
@@ -256,21 +362,18 @@ impl Solver {
     ) -> Option<(Vec<Point>, f64)> {
         if i == self.edge_count {
             let dislike = calculate_dislike(&solution, &self.hole);
-            eprintln!("found!! dislike={}", dislike);
             return Some((solution.clone(), dislike));
         }
 
-        // タイムリミット
-        if *n_iter % 10000 == 0 {
-            let elapsed = Instant::now() - self.start_at;
-            if elapsed >= self.time_limit {
-                return None;
-            }
+        // タイムリミット。下界で枝刈りするときは 1 回が重いので頻繁に確かめる
+        let check_interval = if self.best_dislike.get() < 1e20 { 100 } else { 10000 };
+        if *n_iter % check_interval == 0 && self.budget.is_over(self.iter_base.get() + *n_iter as u64) {
+            return None;
         }
         *n_iter += 1;
         // iteration の回数が多すぎるときは、初期点を選び方を変えたほうが良さそうなので
         // 探索を打ち切る
-        if *n_iter > 10000000 {
+        if *n_iter > self.max_iter() {
             return None;
         }
 
@@ -314,7 +417,10 @@ impl Solver {
                     distance(&solution[center_index], &p) <= radius
                 }
             };
-            if ok {
+            if ok
+                && self.constraints.allows(dst, &p)
+                && is_within_stretch_bounds(&self.stretch_bounds, dst, &p, solution, determined)
+            {
                 candidates.push(p);
             }
         });
@@ -352,6 +458,9 @@ impl Solver {
         for p1 in candidates.iter() {
             if does_line_fit_in_hole(&p0, &p1, &self.hole) {
                 solution[dst] = *p1;
+                if self.cannot_improve(solution, determined) {
+                    continue;
+                }
                 if let Some(ret) = self.dfs(i + 1, order, possible_ranges, solution, determined, n_iter) {
                     determined[dst] = false;
                     return Some(ret);
@@ -363,6 +472,95 @@ impl Solver {
         None
     }
 
+    // 置いていない頂点のうちドメインが最小のものを置いていく。i は置いた頂点の数。
+    // 辺の長さはドメインで、線分が穴に収まるかは置くときに確かめるので、全ての頂点を置けば妥当なポーズになる
+    fn propagating_dfs(
+        &self,
+        i: usize,
+        solution: &mut Vec<Point>,
+        determined: &mut [bool],
+        n_iter: &mut i64,
+        domains: &mut LatticeDomains,
+    ) -> Option<(Vec<Point>, f64)> {
+        if i == self.vertex_count {
+            let dislike = calculate_dislike(solution, &self.hole);
+            return Some((solution.clone(), dislike));
+        }
+
+        // タイムリミット。1 回の絞り込みが重いので頻繁に確かめる
+        if *n_iter % 100 == 0 && self.budget.is_over(self.iter_base.get() + *n_iter as u64) {
+            return None;
+        }
+        *n_iter += 1;
+        if *n_iter > self.max_iter() {
+            return None;
+        }
+
+        let dst = domains.select_unplaced(determined).unwrap();
+        determined[dst] = true;
+
+        // 置き済みの隣の頂点の 1 つを dfs の src の代わりにして、候補の並べ方を dfs と揃える
+        let src = self.out_edges[dst].iter().copied().find(|&w| determined[w]);
+        let mut candidates: Vec<Point> = domains
+            .members(dst)
+            .into_iter()
+            .map(|k| domains.candidate(k))
+            .filter(|p| is_within_stretch_bounds(&self.stretch_bounds, dst, p, solution, determined))
+            .collect();
+
+        candidates.sort_by_key(|p1| {
+            if self.boundary_terminals.contains(&(p1.x() as i64, p1.y() as i64)) {
+                return -100000000;
+            }
+            let src = match src {
+                Some(src) => src,
+                None => return 0,
+            };
+            let p0 = solution[src];
+            let v1 = p1.0 - p0.0;
+            let mut sim = 0.0;
+            for &w in self.out_edges[src].iter() {
+                if determined[w] && w != dst {
+                    let p2 = solution[w];
+                    let v2 = p2.0 - p0.0;
+                    sim += cosine_sim(v1, v2);
+                }
+            }
+            (sim * 100000.0) as i32
+        });
+
+        // 間引く
+        let max_candidates = if self.vertex_count > 30 { 4 } else { 20 };
+        if candidates.len() > max_candidates {
+            candidates = candidates
+                .iter()
+                .step_by(candidates.len() / max_candidates)
+                .copied()
+                .collect();
+        }
+
+        for p1 in candidates.iter() {
+            if !domains.fits_placed_neighbors(dst, p1, solution, determined) {
+                continue;
+            }
+            solution[dst] = *p1;
+            let mark = domains.mark();
+            let found = if domains.place(dst, p1, determined) && !self.cannot_improve(solution, determined) {
+                self.propagating_dfs(i + 1, solution, determined, n_iter, domains)
+            } else {
+                None
+            };
+            domains.undo(mark);
+            if found.is_some() {
+                determined[dst] = false;
+                return found;
+            }
+        }
+
+        determined[dst] = false;
+        None
+    }
+
     /*
     fn search(
         &self, order: &[Edge], possible_ranges: &[PossibleRange],
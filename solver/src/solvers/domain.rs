@@ -0,0 +1,253 @@
+use crate::common::*;
+use crate::constraints::Constraints;
+use std::collections::HashMap;
+
+// 頂点ごとに置ける格子点の集合 (ドメイン) を持ち、頂点を置くたびに隣の頂点のドメインを
+// 辺の長さを満たす点 (RingOffsetTable の差分だけずらした点) に絞り込む (forward checking)。絞り込みは trail に積んでおき、
+// バックトラックで mark まで戻す。
+// 線分が穴に収まるかの判定は ring の点の数だけ行うと絞り込みの大半の時間を占めるので
+// (problem 75 で 20 倍遅くなる)、絞り込んだドメインが VISIBILITY_CHECK_SIZE 以下のときだけ行い、結果は線分ごとに覚えておく。
+// 大きいドメインの点は実際に置くときに fits_placed_neighbors で確かめる
+
+// 線分が穴に収まるかでも絞り込むドメインの大きさの上限
+const VISIBILITY_CHECK_SIZE: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BitSet {
+    words: Vec<u64>,
+}
+
+impl BitSet {
+    pub fn new(n: usize) -> BitSet {
+        BitSet {
+            words: vec![0; n.div_ceil(64)],
+        }
+    }
+
+    pub fn insert(&mut self, i: usize) {
+        self.words[i / 64] |= 1 << (i % 64);
+    }
+
+    pub fn remove(&mut self, i: usize) {
+        self.words[i / 64] &= !(1 << (i % 64));
+    }
+
+    pub fn contains(&self, i: usize) -> bool {
+        self.words[i / 64] >> (i % 64) & 1 == 1
+    }
+
+    pub fn len(&self) -> usize {
+        self.words.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.iter().all(|&w| w == 0)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.words.iter().enumerate().flat_map(|(i, &w)| {
+            let mut w = w;
+            std::iter::from_fn(move || {
+                if w == 0 {
+                    return None;
+                }
+                let b = w.trailing_zeros() as usize;
+                w &= w - 1;
+                Some(i * 64 + b)
+            })
+        })
+    }
+}
+
+pub struct LatticeDomains<'a> {
+    input: &'a Input,
+    out_edges: Vec<Vec<usize>>,
+    offset_table: RingOffsetTable,
+    // 格子点の番号は candidates の添字。探索はこの順に候補を試す
+    candidates: Vec<Point>,
+    index: HashMap<(i64, i64), usize>,
+    domains: Vec<BitSet>,
+    sizes: Vec<usize>,
+    // (頂点, 絞り込む前のドメイン, その大きさ)
+    trail: Vec<(usize, BitSet, usize)>,
+    // (格子点の番号, 格子点の番号) を結ぶ線分が穴に収まるか。番号の小さい方を先にする
+    visible: HashMap<(usize, usize), bool>,
+}
+
+impl LatticeDomains<'_> {
+    // candidates は穴の中の格子点を試したい順に並べたもの。制約で許されない点ははじめから除く
+    pub fn new<'a>(
+        input: &'a Input,
+        candidates: Vec<Point>,
+        constraints: &Constraints,
+    ) -> LatticeDomains<'a> {
+        let n = input.figure.vertices.len();
+        let index = candidates
+            .iter()
+            .enumerate()
+            .map(|(k, p)| ((p.x() as i64, p.y() as i64), k))
+            .collect();
+        let domains: Vec<BitSet> = (0..n)
+            .map(|i| {
+                let mut domain = BitSet::new(candidates.len());
+                for (k, p) in candidates.iter().enumerate() {
+                    if constraints.allows(i, p) {
+                        domain.insert(k);
+                    }
+                }
+                domain
+            })
+            .collect();
+        let sizes = domains.iter().map(|d| d.len()).collect();
+        LatticeDomains {
+            input,
+            out_edges: make_out_edges(&input.figure.edges, n),
+            offset_table: RingOffsetTable::new(input),
+            candidates,
+            index,
+            domains,
+            sizes,
+            trail: vec![],
+            visible: HashMap::new(),
+        }
+    }
+
+    pub fn candidate(&self, k: usize) -> Point {
+        self.candidates[k]
+    }
+
+    pub fn index_of(&self, p: &Point) -> Option<usize> {
+        self.index.get(&(p.x() as i64, p.y() as i64)).copied()
+    }
+
+    pub fn contains(&self, i: usize, p: &Point) -> bool {
+        self.index_of(p)
+            .is_some_and(|k| self.domains[i].contains(k))
+    }
+
+    pub fn members(&self, i: usize) -> Vec<usize> {
+        self.domains[i].iter().collect()
+    }
+
+    // p に置いた頂点 v と、置き済みの隣の頂点を結ぶ線分が全て穴に収まるか
+    pub fn fits_placed_neighbors(
+        &self,
+        v: usize,
+        p: &Point,
+        vertices: &[Point],
+        placed: &[bool],
+    ) -> bool {
+        self.out_edges[v]
+            .iter()
+            .all(|&w| !placed[w] || does_line_fit_in_hole(p, &vertices[w], &self.input.hole))
+    }
+
+    // まだ置いていない頂点のうちドメインが最小のもの (MRV)。同じなら次数の大きいもの
+    pub fn select_unplaced(&self, placed: &[bool]) -> Option<usize> {
+        (0..placed.len())
+            .filter(|&i| !placed[i])
+            .min_by_key(|&i| (self.sizes[i], std::cmp::Reverse(self.out_edges[i].len()), i))
+    }
+
+    pub fn mark(&self) -> usize {
+        self.trail.len()
+    }
+
+    pub fn undo(&mut self, mark: usize) {
+        while self.trail.len() > mark {
+            let (i, domain, size) = self.trail.pop().unwrap();
+            self.domains[i] = domain;
+            self.sizes[i] = size;
+        }
+    }
+
+    // 頂点 v を p に置いたとして、まだ置いていない隣の頂点のドメインを辺の長さで絞り込み、
+    // 小さくなったドメインは p からの線分が穴に収まる点に絞り込む。
+    // 空になったドメインがあれば false を返す (絞り込みは undo で戻すこと)
+    pub fn place(&mut self, v: usize, p: &Point, placed: &[bool]) -> bool {
+        let input = self.input;
+        let (x, y) = (p.x() as i64, p.y() as i64);
+        let from = self.index_of(p);
+        for t in 0..self.out_edges[v].len() {
+            let w = self.out_edges[v][t];
+            if placed[w] {
+                continue;
+            }
+            let length = original_squared_length(input, &Edge::new(v, w));
+            let mut domain = BitSet::new(self.candidates.len());
+            let mut size = 0;
+            for &(dx, dy) in self.offset_table.offsets(length) {
+                let k = match self.index.get(&(x + dx, y + dy)) {
+                    Some(&k) => k,
+                    None => continue,
+                };
+                if self.domains[w].contains(k) {
+                    domain.insert(k);
+                    size += 1;
+                }
+            }
+            if size <= VISIBILITY_CHECK_SIZE {
+                let members: Vec<usize> = domain.iter().collect();
+                for k in members {
+                    if !self.is_visible(from, p, k) {
+                        domain.remove(k);
+                        size -= 1;
+                    }
+                }
+            }
+            let old = std::mem::replace(&mut self.domains[w], domain);
+            self.trail.push((w, old, self.sizes[w]));
+            self.sizes[w] = size;
+            if size == 0 {
+                return false;
+            }
+        }
+        true
+    }
+
+    // p (格子点の番号は from) と格子点 k を結ぶ線分が穴に収まるか
+    fn is_visible(&mut self, from: Option<usize>, p: &Point, k: usize) -> bool {
+        let q = self.candidates[k];
+        let hole = &self.input.hole;
+        match from {
+            Some(from) => *self
+                .visible
+                .entry((from.min(k), from.max(k)))
+                .or_insert_with(|| does_line_fit_in_hole(p, &q, hole)),
+            None => does_line_fit_in_hole(p, &q, hole),
+        }
+    }
+}
+
+#[test]
+fn test_lattice_domains() {
+    let input = crate::inout::parse_input(
+        r#"{"hole":[[0,0],[4,0],[4,4],[0,4]],"epsilon":0,"figure":{"edges":[[0,1],[1,2]],"vertices":[[0,0],[4,0],[4,4]]},"bonuses":[]}"#,
+    );
+    let candidates = lattice_points_in_hole(&input.hole);
+    let mut domains = LatticeDomains::new(&input, candidates, &Constraints::default());
+    let mut placed = vec![false; 3];
+    // 次数が最大の頂点 1 から置く
+    assert_eq!(domains.select_unplaced(&placed), Some(1));
+    let mark = domains.mark();
+    placed[1] = true;
+    assert!(domains.place(1, &Point::new(0.0, 0.0), &placed));
+    // (0,0) から長さ 4 で届く格子点は (4,0) と (0,4) だけ
+    assert_eq!(domains.members(0).len(), 2);
+    assert!(domains.contains(2, &Point::new(0.0, 4.0)));
+    assert!(!domains.contains(2, &Point::new(2.0, 2.0)));
+    domains.undo(mark);
+    assert_eq!(domains.members(0).len(), 25);
+    // 中央に置くと長さ 4 の辺の行き先は穴の外しかない
+    assert!(!domains.place(1, &Point::new(2.0, 2.0), &placed));
+
+    // L 字の穴では (1,4) から長さ 5 で届く格子点 (4,0) への線分が穴の外を通る
+    let input = crate::inout::parse_input(
+        r#"{"hole":[[0,0],[4,0],[4,1],[1,1],[1,4],[0,4]],"epsilon":0,"figure":{"edges":[[0,1]],"vertices":[[0,0],[3,4]]},"bonuses":[]}"#,
+    );
+    let candidates = lattice_points_in_hole(&input.hole);
+    let mut domains = LatticeDomains::new(&input, candidates, &Constraints::default());
+    let placed = vec![true, false];
+    assert!(domains.contains(1, &Point::new(4.0, 0.0)));
+    assert!(!domains.place(0, &Point::new(1.0, 4.0), &placed));
+}
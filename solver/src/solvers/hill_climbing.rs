@@ -1,6 +1,11 @@
+use crate::anytime;
+use crate::budget::Budget;
 use crate::common::*;
+use crate::constraints::Constraints;
+use crate::solvers::moves::{movable_vertices, CornerMoves, VertexMoves};
+use crate::stats::{StageStats, StopReason};
+use crate::trace::Tracer;
 use rand::prelude::*;
-use std::time::{Duration, Instant};
 
 static SEED: [u8; 32] = [
     0xfd, 0x00, 0xf1, 0x5c, 0xde, 0x01, 0x11, 0xc6, 0xc3, 0xea, 0xfb, 0xbf, 0xf3, 0xca, 0xd8, 0x32,
@@ -10,88 +15,112 @@ static SEED: [u8; 32] = [
 pub fn solve(
     input: &Input,
     mut solution: Vec<Point>,
-    time_limit: Duration,
-    fix_seed: bool,
-) -> (Vec<Point>, f64) {
+    budget: &Budget,
+    seed: Option<u64>,
+    corner_move_rate: f64,
+    constraints: &Constraints,
+    mut tracer: Option<&mut Tracer>,
+) -> (Vec<Point>, f64, StageStats) {
+    let mut stats = StageStats::start("hill_climbing");
     let n = solution.len();
-    let mut rng = if fix_seed {
-        SmallRng::from_seed(SEED)
-    } else {
-        SmallRng::from_entropy()
-    };
+    let mut rng = make_rng(&SEED, seed);
     let mut current_score = calculate_dislike(&solution, &input.hole);
-    let out_edges = make_out_edges(&input.figure.edges, n);
-    let original_vertices = &input.figure.vertices;
-    let start_at = Instant::now();
+    let free_vertices = match movable_vertices(constraints, n) {
+        Some(vertices) => vertices,
+        None => {
+            let dislike = calculate_dislike(&solution, &input.hole);
+            let stats = stats.finish(0, StopReason::Completed, Some(dislike));
+            return (solution, dislike, stats);
+        }
+    };
+    let vertex_moves = VertexMoves::new(input, constraints);
+    let corner_moves = CornerMoves::new(input, constraints);
 
-    let mut iter = 0;
+    let mut iter: u64 = 0;
     loop {
         // check time limit
         iter += 1;
         if iter % 100 == 0 {
-            let elapsed = Instant::now() - start_at;
-            if current_score == 0.0 || elapsed >= time_limit {
-                eprintln!("Hill Climbing Total Iteration: {}", iter);
-                return (solution, current_score);
+            if current_score == 0.0 || budget.is_over(iter) {
+                let reason = if current_score == 0.0 {
+                    StopReason::OptimalFound
+                } else {
+                    budget.stop_reason()
+                };
+                let stats = stats.finish(iter, reason, Some(current_score));
+                return (solution, current_score, stats);
             }
         }
 
-        // modify solution
-        let i = rng.gen::<usize>() % n;
-        let candidates = make_next_candidates(
-            i,
-            original_vertices,
-            &input.hole,
-            input.epsilon,
-            &solution,
-            &out_edges,
-        );
-        let candidate = candidates[rng.gen_range(0..candidates.len())];
+        // modify solution. corner_move_rate の割合で穴の頂点に図形の頂点を寄せる
+        let mut old_solution = None;
+        let mut old_vertex = None;
+        if corner_move_rate > 0.0 && rng.gen::<f64>() < corner_move_rate {
+            match corner_moves.propose(&solution, &mut rng) {
+                Some(next) => old_solution = Some(std::mem::replace(&mut solution, next)),
+                None => {
+                    if let Some(tracer) = tracer.as_mut() {
+                        tracer.record_move(false);
+                    }
+                    continue;
+                }
+            }
+        } else {
+            let (i, candidate) =
+                match vertex_moves.random_move(&free_vertices, &solution, &mut rng) {
+                    Some(m) => m,
+                    // 穴の頂点に寄せる近傍でまだ動かせるかもしれないので、予算が尽きるまで続ける
+                    None if corner_move_rate > 0.0 => {
+                        if let Some(tracer) = tracer.as_mut() {
+                            tracer.record_move(false);
+                        }
+                        continue;
+                    }
+                    None => {
+                        // どの頂点も動かせない
+                        let stats = stats.finish(iter, StopReason::Completed, Some(current_score));
+                        return (solution, current_score, stats);
+                    }
+                };
+            old_vertex = Some((i, solution[i]));
+            solution[i] = candidate;
+        }
 
         // calculate score. FIXME: slow
-        let old = solution[i];
-        solution[i] = candidate;
         let new_score = calculate_dislike(&solution, &input.hole);
 
-        if new_score < current_score {
+        let accept = new_score < current_score;
+        if accept {
             // accept candidate
             current_score = new_score;
+            stats.accepted_moves += 1;
+            stats.record_best(iter, current_score);
+            anytime::offer(&solution);
         } else {
             // reject candidate
-            solution[i] = old;
+            if let Some((i, old)) = old_vertex {
+                solution[i] = old;
+            }
+            if let Some(old) = old_solution {
+                solution = old;
+            }
         }
-    }
-}
-
-fn make_next_candidates(
-    i: usize,
-    original_vertices: &[Point],
-    hole: &Polygon,
-    epsilon: i64,
-    solution: &[Point],
-    out_edges: &[Vec<usize>],
-) -> Vec<Point> {
-    let some_neighbor = out_edges[i][0];
-    let original_squared_distance =
-        squared_distance(&original_vertices[i], &original_vertices[some_neighbor]);
-    let ring = Ring::from_epsilon(solution[some_neighbor], epsilon, original_squared_distance);
 
-    let mut candidates = vec![];
-    for &p in ring_points(&ring).iter() {
-        let ok = out_edges[i].iter().all(|&dst| {
-            is_allowed_distance(
-                &p,
-                &solution[dst],
-                &original_vertices[i],
-                &original_vertices[dst],
-                epsilon,
-                false,
-            ) && does_line_fit_in_hole(&p, &solution[dst], hole)
-        });
-        if ok {
-            candidates.push(p);
+        if let Some(tracer) = tracer.as_mut() {
+            tracer.record_move(accept);
+            if tracer.should_snapshot(iter) {
+                tracer.snapshot(
+                    "hill_climbing",
+                    iter,
+                    budget.elapsed().as_secs_f64(),
+                    budget.progress(iter),
+                    0.0,
+                    &solution,
+                    &solution,
+                    &input.hole,
+                );
+            }
         }
     }
-
-    candidates
 }
+
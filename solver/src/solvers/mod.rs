@@ -3,7 +3,10 @@ pub mod annealing;
 pub mod annealing3;
 pub mod dfs;
 pub mod dfs2;
+pub mod domain;
 pub mod hill_climbing;
+pub mod moves;
 pub mod orthgonal;
+pub mod penalty_annealing;
 pub mod physical;
 pub mod shrink;
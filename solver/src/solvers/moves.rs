@@ -0,0 +1,288 @@
+use crate::common::*;
+use crate::constraints::Constraints;
+use rand::prelude::*;
+
+// 頂点を 1 つ動かす近傍。隣の頂点全ての ring の共通部分 (RingOffsetTable) を列挙し、
+// 穴の中の格子点に入っていて、固定された頂点から届き、隣の頂点との線分が穴に収まる位置だけを残す。
+// 行き先が無い頂点を選んで反復を無駄にしないように、動かせる頂点を探してから返す
+pub struct VertexMoves<'a> {
+    input: &'a Input,
+    constraints: &'a Constraints,
+    out_edges: Vec<Vec<usize>>,
+    offset_table: RingOffsetTable,
+    stretch_bounds: Vec<Vec<f64>>,
+    // 固定された頂点。無ければ距離の上限は見ない
+    pinned: Vec<bool>,
+    has_pinned: bool,
+    // 穴の bounding box の格子点が穴の中にあるか。(x - min_x) + (y - min_y) * width の順
+    min_x: i64,
+    min_y: i64,
+    width: i64,
+    height: i64,
+    in_hole: Vec<bool>,
+}
+
+impl VertexMoves<'_> {
+    pub fn new<'a>(input: &'a Input, constraints: &'a Constraints) -> VertexMoves<'a> {
+        let hole_points: Vec<Point> = input.hole.exterior().points_iter().collect();
+        let (min, max) = calc_bound_box(&hole_points);
+        let min_x = min.x().ceil() as i64;
+        let min_y = min.y().ceil() as i64;
+        let width = max.x().floor() as i64 - min_x + 1;
+        let height = max.y().floor() as i64 - min_y + 1;
+        let mut in_hole = vec![false; (width * height) as usize];
+        for p in lattice_points_in_hole(&input.hole) {
+            in_hole[((p.x() as i64 - min_x) + (p.y() as i64 - min_y) * width) as usize] = true;
+        }
+        let n = input.figure.vertices.len();
+        let pinned: Vec<bool> = (0..n).map(|i| constraints.is_fixed(i)).collect();
+        VertexMoves {
+            input,
+            constraints,
+            out_edges: make_out_edges(&input.figure.edges, n),
+            offset_table: RingOffsetTable::new(input),
+            stretch_bounds: make_stretch_bounds(input),
+            has_pinned: pinned.iter().any(|&b| b),
+            pinned,
+            min_x,
+            min_y,
+            width,
+            height,
+            in_hole,
+        }
+    }
+
+    fn is_in_hole(&self, p: &Point) -> bool {
+        let x = p.x() as i64 - self.min_x;
+        let y = p.y() as i64 - self.min_y;
+        0 <= x
+            && x < self.width
+            && 0 <= y
+            && y < self.height
+            && self.in_hole[(x + y * self.width) as usize]
+    }
+
+    // 辺の長さを満たす今と違う位置のうち、穴の中の格子点にあって制約を満たし、固定された頂点から届くもの。
+    // 線分はまだ見ない
+    fn ring_candidates(&self, i: usize, solution: &[Point]) -> Vec<Point> {
+        let mut ps = self.offset_table.common_points(
+            i,
+            solution,
+            &self.input.figure.vertices,
+            &self.out_edges,
+        );
+        ps.retain(|p| {
+            *p != solution[i]
+                && self.is_in_hole(p)
+                && self.constraints.allows(i, p)
+                && (!self.has_pinned
+                    || is_within_stretch_bounds(&self.stretch_bounds, i, p, solution, &self.pinned))
+        });
+        ps
+    }
+
+    fn do_lines_fit(&self, i: usize, p: &Point, solution: &[Point]) -> bool {
+        self.out_edges[i]
+            .iter()
+            .all(|&w| does_line_fit_in_hole(p, &solution[w], &self.input.hole))
+    }
+
+    // 頂点 i を今と違う位置に動かして、辺の長さ、穴、制約を全て満たす位置。無ければ None
+    pub fn moves(&self, i: usize, solution: &[Point]) -> Option<Vec<Point>> {
+        let mut ps = self.ring_candidates(i, solution);
+        ps.retain(|p| self.do_lines_fit(i, p, solution));
+        if ps.is_empty() {
+            None
+        } else {
+            Some(ps)
+        }
+    }
+
+    // candidates から重複なくランダムに引いた順に見て、最初に動かせた頂点と、その行き先 (moves から一様に選んだもの)。
+    // 動かせる頂点はどれも同じ確率で選ばれる。
+    // 線分の判定が重いので、行き先は全て列挙せずにランダムに引いたものから確かめる。
+    // どの頂点も動かせなければ None
+    pub fn random_move(
+        &self,
+        candidates: &[usize],
+        solution: &[Point],
+        rng: &mut SmallRng,
+    ) -> Option<(usize, Point)> {
+        let mut rest = candidates.to_vec();
+        while !rest.is_empty() {
+            let i = rest.swap_remove(rng.gen_range(0..rest.len()));
+            let mut ps = self.ring_candidates(i, solution);
+            while !ps.is_empty() {
+                let p = ps.swap_remove(rng.gen_range(0..ps.len()));
+                if self.do_lines_fit(i, &p, solution) {
+                    return Some((i, p));
+                }
+            }
+        }
+        None
+    }
+}
+
+// 固定されていない頂点。全ての頂点が固定されていれば動かせるものがないので None
+pub fn movable_vertices(constraints: &Constraints, n: usize) -> Option<Vec<usize>> {
+    let vertices = constraints.free_vertices(n);
+    if vertices.is_empty() {
+        None
+    } else {
+        Some(vertices)
+    }
+}
+
+// 穴の頂点のうち dislike への寄与が大きいものを選び、最も近い図形の頂点をその頂点に動かす近傍。
+// ずれた辺は fix_allowed_distance_violation で隣の頂点を動かして直す
+pub struct CornerMoves<'a> {
+    input: &'a Input,
+    constraints: &'a Constraints,
+    out_edges: Vec<Vec<usize>>,
+    orders: Vec<Vec<usize>>,
+    stretch_bounds: Vec<Vec<f64>>,
+    pinned: Vec<bool>,
+}
+
+impl CornerMoves<'_> {
+    pub fn new<'a>(input: &'a Input, constraints: &'a Constraints) -> CornerMoves<'a> {
+        let n = input.figure.vertices.len();
+        let out_edges = make_out_edges(&input.figure.edges, n);
+        let orders = (0..n)
+            .map(|i| make_determined_order(&out_edges, Some(i)))
+            .collect();
+        CornerMoves {
+            input,
+            constraints,
+            out_edges,
+            orders,
+            stretch_bounds: make_stretch_bounds(input),
+            pinned: (0..n).map(|i| constraints.is_fixed(i)).collect(),
+        }
+    }
+
+    // 穴の頂点を (最も近い図形の頂点までの距離の 2 乗) に比例する確率で選び、
+    // 最も近い固定されていない頂点をそこに動かしたポーズ。
+    // dislike が 0 か、固定された頂点から届かないか、直せなければ None
+    pub fn propose(&self, solution: &[Point], rng: &mut SmallRng) -> Option<Vec<Point>> {
+        let corners: Vec<Point> = self.input.hole.exterior().points_iter().skip(1).collect();
+        let contributions: Vec<f64> = corners
+            .iter()
+            .map(|h| {
+                solution
+                    .iter()
+                    .map(|v| squared_distance(v, h))
+                    .fold(f64::INFINITY, f64::min)
+            })
+            .collect();
+        let total: f64 = contributions.iter().sum();
+        if total == 0.0 {
+            return None;
+        }
+        let mut r = rng.gen::<f64>() * total;
+        let mut corner = corners[corners.len() - 1];
+        for (h, c) in corners.iter().zip(contributions.iter()) {
+            if r < *c {
+                corner = *h;
+                break;
+            }
+            r -= c;
+        }
+
+        let i = (0..solution.len())
+            .filter(|&i| !self.constraints.is_fixed(i))
+            .min_by(|&a, &b| {
+                squared_distance(&solution[a], &corner)
+                    .partial_cmp(&squared_distance(&solution[b], &corner))
+                    .unwrap()
+            })?;
+        if !self.constraints.allows(i, &corner)
+            || !is_within_stretch_bounds(&self.stretch_bounds, i, &corner, solution, &self.pinned)
+        {
+            return None;
+        }
+        let mut moved = solution.to_vec();
+        moved[i] = corner;
+        fix_allowed_distance_violation(i, &moved, self.input, &self.out_edges, &self.orders, None)
+            .into_valid()
+            .filter(|s| self.constraints.is_satisfied(s))
+    }
+}
+
+#[test]
+fn test_vertex_moves() {
+    let input = crate::inout::parse_input(
+        r#"{"hole":[[0,0],[10,0],[10,10],[0,10]],"epsilon":0,"figure":{"edges":[[0,1],[1,2]],"vertices":[[0,0],[5,0],[5,5]]},"bonuses":[]}"#,
+    );
+    let constraints = Constraints::default();
+    let moves = VertexMoves::new(&input, &constraints);
+    let solution = input.figure.vertices.clone();
+    // 頂点 1 は (0,0) と (5,5) のどちらからも 5 離れた位置で、今の (5,0) 以外は (0,5) だけ
+    assert_eq!(moves.moves(1, &solution), Some(vec![Point::new(0.0, 5.0)]));
+    // 頂点 0 は (5,0) を中心に半径 5 の格子点のうち穴の中にあるもの
+    let ps = moves.moves(0, &solution).unwrap();
+    assert_eq!(ps.len(), 6);
+    assert!(ps.iter().all(|p| does_point_fit_in_hole(p, &input.hole)));
+
+    // 直角の頂点 0 は (5,5) に折り返せるが、頂点 1, 2 の行き先は穴の外しかない
+    let input = crate::inout::parse_input(
+        r#"{"hole":[[0,0],[10,0],[10,10],[0,10]],"epsilon":0,"figure":{"edges":[[0,1],[1,2],[2,0]],"vertices":[[0,0],[5,0],[0,5]]},"bonuses":[]}"#,
+    );
+    let moves = VertexMoves::new(&input, &constraints);
+    let solution = input.figure.vertices.clone();
+    assert_eq!(moves.moves(1, &solution), None);
+    assert_eq!(moves.moves(2, &solution), None);
+    let mut rng = make_rng(&[0; 32], Some(0));
+    for _ in 0..10 {
+        assert_eq!(
+            moves.random_move(&[0, 1, 2], &solution, &mut rng),
+            Some((0, Point::new(5.0, 5.0)))
+        );
+    }
+    assert_eq!(moves.random_move(&[1, 2], &solution, &mut rng), None);
+
+    // 動かせるのは頂点 0 と 3 だけ。動かせない頂点 1, 2 の後ろにある 3 も同じ確率で選ばれる
+    let input = crate::inout::parse_input(
+        r#"{"hole":[[0,0],[10,0],[10,10],[0,10]],"epsilon":0,"figure":{"edges":[[0,1],[1,2],[2,0],[3,4],[4,5],[5,3]],"vertices":[[0,0],[5,0],[0,5],[10,10],[5,10],[10,5]]},"bonuses":[]}"#,
+    );
+    let moves = VertexMoves::new(&input, &constraints);
+    let solution = input.figure.vertices.clone();
+    let mut n_first = 0;
+    for _ in 0..1000 {
+        let (i, p) = moves.random_move(&[0, 1, 2, 3], &solution, &mut rng).unwrap();
+        assert_eq!(p, Point::new(5.0, 5.0));
+        if i == 0 {
+            n_first += 1;
+        }
+    }
+    assert!(400 < n_first && n_first < 600, "{}", n_first);
+}
+
+#[test]
+fn test_corner_moves() {
+    let input = crate::inout::parse_input(
+        r#"{"hole":[[0,0],[10,0],[10,10],[0,10]],"epsilon":0,"figure":{"edges":[[0,1]],"vertices":[[1,0],[5,3]]},"bonuses":[]}"#,
+    );
+    let constraints = Constraints::default();
+    let moves = CornerMoves::new(&input, &constraints);
+    let mut rng = make_rng(&[0; 32], Some(0));
+    let solution = input.figure.vertices.clone();
+    let corners: Vec<Point> = input.hole.exterior().points_iter().collect();
+    let mut n_proposed = 0;
+    for _ in 0..20 {
+        if let Some(next) = moves.propose(&solution, &mut rng) {
+            // どこかの穴の頂点に図形の頂点が乗り、辺は直っている
+            assert!(next.iter().any(|p| corners.contains(p)));
+            assert!(does_valid_pose(&next, &input.figure, &input.hole, input.epsilon, &vec![], None));
+            n_proposed += 1;
+        }
+    }
+    assert!(n_proposed > 0);
+
+    // dislike が 0 なら動かすものがない
+    let covered = crate::inout::parse_input(
+        r#"{"hole":[[0,0],[4,0],[4,3]],"epsilon":0,"figure":{"edges":[[0,1],[1,2]],"vertices":[[0,0],[4,0],[4,3]]},"bonuses":[]}"#,
+    );
+    let moves = CornerMoves::new(&covered, &constraints);
+    assert_eq!(moves.propose(&covered.figure.vertices, &mut rng), None);
+}
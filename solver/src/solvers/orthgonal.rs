@@ -1,8 +1,27 @@
+use crate::budget::Budget;
 use crate::common::*;
+use crate::stats::{StageStats, StopReason};
 
 #[allow(dead_code)]
-pub fn solve(input: &Input) -> Option<(Vec<Point>, f64)> {
-    return try_all_translations_rotations_and_mirrors(&input.figure, &input.hole);
+pub fn solve(input: &Input, budget: &Budget) -> (Option<(Vec<Point>, f64)>, StageStats) {
+    let stats = StageStats::start("orthgonal");
+    let mut n_iter = 0;
+    let ret = try_all_translations_rotations_and_mirrors(
+        &input.figure,
+        &input.hole,
+        budget,
+        &mut n_iter,
+    );
+    let reason = if budget.is_over(n_iter) {
+        budget.stop_reason()
+    } else {
+        StopReason::Completed
+    };
+    let stats = match ret {
+        Some((_, dislike)) => stats.finish(n_iter, reason, Some(dislike)),
+        None => stats.finish(n_iter, StopReason::NotFound, None),
+    };
+    (ret, stats)
 }
 
 fn translate(src: &Figure, dx: f64, dy: f64, dest: &mut Figure) {
@@ -28,6 +47,8 @@ fn try_all_translations(
     original_figure: &Figure,
     hole: &Polygon,
     best_dislike: f64,
+    budget: &Budget,
+    n_iter: &mut u64,
 ) -> Option<(Vec<Point>, f64)> {
     let mut figure = original_figure.clone();
     let mut best_vertices = None;
@@ -57,10 +78,15 @@ fn try_all_translations(
         if dy.abs() % y_step != 0 {
             continue;
         }
+        // タイムリミットを超えたらそれまでの最良を返す
+        if budget.is_over(*n_iter) {
+            break;
+        }
         for dx in u..=b {
             if dx.abs() % x_step != 0 {
                 continue;
             }
+            *n_iter += 1;
             translate(original_figure, dx as f64, dy as f64, &mut figure);
             let dislike = calculate_dislike(&figure.vertices, hole);
             if dislike >= best_dislike {
@@ -78,6 +104,8 @@ fn try_all_translations(
 fn try_all_translations_rotations_and_mirrors(
     original_figure: &Figure,
     hole: &Polygon,
+    budget: &Budget,
+    n_iter: &mut u64,
 ) -> Option<(Vec<Point>, f64)> {
     let mut figure = original_figure.clone();
     let mut best_vertices = None;
@@ -88,7 +116,7 @@ fn try_all_translations_rotations_and_mirrors(
     }
     for _i in 0..2 {
         for _j in 0..4 {
-            if let Some((vs, dislike)) = try_all_translations(&figure, hole, best_dislike) {
+            if let Some((vs, dislike)) = try_all_translations(&figure, hole, best_dislike, budget, n_iter) {
                 if dislike < best_dislike {
                     best_vertices = Some(vs);
                     best_dislike = dislike;
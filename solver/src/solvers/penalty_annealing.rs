@@ -0,0 +1,337 @@
+use crate::anytime;
+use crate::budget::Budget;
+use crate::common::*;
+use crate::constraints::Constraints;
+use crate::solvers::moves::movable_vertices;
+use crate::stats::{StageStats, StopReason};
+use crate::trace::Tracer;
+use rand::prelude::*;
+
+static SEED: [u8; 32] = [
+    0xfd, 0x00, 0xf1, 0x5c, 0xde, 0x01, 0x11, 0xc6, 0xc3, 0xea, 0xfb, 0xbf, 0xf3, 0xca, 0xd8, 0x32,
+    0x6a, 0xe3, 0x07, 0x99, 0xc5, 0xe0, 0x52, 0xe4, 0xaa, 0x35, 0x07, 0x99, 0xe3, 0x2b, 0x9d, 0xc6,
+];
+
+// 違反の重みは最初 INITIAL_WEIGHT で、最後に FINAL_WEIGHT になるように指数的に増やす
+const INITIAL_WEIGHT: f64 = 0.1;
+const FINAL_WEIGHT: f64 = 1000.0;
+// initial_temperature は分散の項を含む他の焼きなましの評価値に合わせてあるので、
+// dislike と違反だけの評価値ではこの倍率を掛けて使う (既定の INITIAL_TEMPERATURE = 10000 なら 100 から始まる)
+const TEMPERATURE_SCALE: f64 = 0.01;
+
+// 辺の長さや穴の制約を破ったポーズも通れる焼きなまし。
+// 評価値は dislike + 重み × (辺の伸び縮みの超過 + 穴の外に出た線分の長さ^2) で、重みは時間とともに大きくする。
+// 最良解として記録するのは全ての辺が妥当なポーズだけで、見つからなければ渡されたポーズを返す
+pub fn solve(
+    input: &Input,
+    mut solution: Vec<Point>,
+    budget: &Budget,
+    seed: Option<u64>,
+    initial_temperature: f64,
+    constraints: &Constraints,
+    mut tracer: Option<&mut Tracer>,
+) -> (Vec<Point>, f64, StageStats) {
+    let mut stats = StageStats::start("penalty_annealing");
+    let n = solution.len();
+    let mut rng = make_rng(&SEED, seed);
+    let out_edges = make_out_edges(&input.figure.edges, n);
+    let free_vertices = match movable_vertices(constraints, n) {
+        Some(vertices) => vertices,
+        None => {
+            let dislike = calculate_dislike(&solution, &input.hole);
+            let stats = stats.finish(0, StopReason::Completed, Some(dislike));
+            return (solution, dislike, stats);
+        }
+    };
+    let offset_table = RingOffsetTable::new(input);
+    // 固定された頂点から届かない位置は、違反を許しても妥当なポーズに戻れないので動かさない
+    let pinned: Vec<bool> = (0..n).map(|i| constraints.is_fixed(i)).collect();
+    let stretch_bounds = make_stretch_bounds(input);
+
+    // 頂点ごとの (隣の頂点, 辺の番号)
+    let mut incident = vec![vec![]; n];
+    for (k, e) in input.figure.edges.iter().enumerate() {
+        incident[e.v].push((e.w, k));
+        incident[e.w].push((e.v, k));
+    }
+    let mut edge_penalties: Vec<(f64, bool)> = input
+        .figure
+        .edges
+        .iter()
+        .map(|e| calc_edge_penalty(input, e.v, e.w, &solution[e.v], &solution[e.w]))
+        .collect();
+    let mut penalty: f64 = edge_penalties.iter().map(|(p, _)| p).sum();
+    let mut n_invalid_edges = edge_penalties.iter().filter(|(_, ok)| !ok).count();
+    let mut dislike = calculate_dislike(&solution, &input.hole);
+
+    // 渡されたポーズが妥当でなければ、妥当なポーズが見つかるまで最良解は無い
+    let mut best_solution = solution.clone();
+    let mut best_dislike = if n_invalid_edges == 0 { dislike } else { f64::INFINITY };
+
+    let mut progress = 0.0;
+    let initial_temperature = initial_temperature * TEMPERATURE_SCALE;
+    let mut temperature = initial_temperature;
+    let mut weight = INITIAL_WEIGHT;
+    eprintln!("initial_temperature = {}", initial_temperature);
+
+    let mut iter: u64 = 0;
+    loop {
+        // check time limit
+        iter += 1;
+        if iter.is_multiple_of(100) {
+            if best_dislike == 0.0 || budget.is_over(iter) {
+                if best_dislike.is_infinite() {
+                    // 妥当なポーズが見つからなかったので、渡されたポーズをそのまま返す
+                    let dislike = calculate_dislike(&best_solution, &input.hole);
+                    let stats = stats.finish(iter, StopReason::NotFound, None);
+                    return (best_solution, dislike, stats);
+                }
+                let reason = if best_dislike == 0.0 {
+                    StopReason::OptimalFound
+                } else {
+                    budget.stop_reason()
+                };
+                let stats = stats.finish(iter, reason, Some(best_dislike));
+                return (best_solution, best_dislike, stats);
+            }
+
+            // tweak temperature and weight
+            progress = budget.progress(iter);
+            temperature = initial_temperature * (1.0 - progress) * (-progress).exp2();
+            weight = INITIAL_WEIGHT * (FINAL_WEIGHT / INITIAL_WEIGHT).powf(progress);
+        }
+
+        // move to neighbor. 隣の頂点全ての ring の共通部分に乗せるか、1 つの隣の頂点の ring に乗せるか、少しずらす
+        let i = free_vertices[rng.gen_range(0..free_vertices.len())];
+        let r = rng.gen_range(0..3);
+        let candidate = if r == 0 {
+            let ps = offset_table.common_points(i, &solution, &input.figure.vertices, &out_edges);
+            if ps.is_empty() {
+                solution[i]
+            } else {
+                ps[rng.gen_range(0..ps.len())]
+            }
+        } else if r == 1 && !incident[i].is_empty() {
+            let (w, k) = incident[i][rng.gen_range(0..incident[i].len())];
+            let offsets = offset_table.offsets(original_squared_length(input, &input.figure.edges[k]));
+            let (dx, dy) = offsets[rng.gen_range(0..offsets.len())];
+            solution[w] + Point::new(dx as f64, dy as f64)
+        } else {
+            let dx = rng.gen_range(-2..=2) as f64;
+            let dy = rng.gen_range(-2..=2) as f64;
+            solution[i] + Point::new(dx, dy)
+        };
+        if candidate == solution[i]
+            || !constraints.allows(i, &candidate)
+            || !is_within_stretch_bounds(&stretch_bounds, i, &candidate, &solution, &pinned)
+        {
+            if let Some(tracer) = tracer.as_mut() {
+                tracer.record_move(false);
+            }
+            continue;
+        }
+
+        let old = solution[i];
+        solution[i] = candidate;
+        let new_edge_penalties: Vec<(f64, bool)> = incident[i]
+            .iter()
+            .map(|&(w, _)| calc_edge_penalty(input, i, w, &solution[i], &solution[w]))
+            .collect();
+        let mut new_penalty = penalty;
+        let mut new_n_invalid_edges = n_invalid_edges as i64;
+        for (&(_, k), &(p, ok)) in incident[i].iter().zip(new_edge_penalties.iter()) {
+            new_penalty += p - edge_penalties[k].0;
+            new_n_invalid_edges += edge_penalties[k].1 as i64 - ok as i64;
+        }
+        // calculate score. FIXME: slow
+        let new_dislike = calculate_dislike(&solution, &input.hole);
+
+        let current = dislike + weight * penalty;
+        let new = new_dislike + weight * new_penalty;
+        let accept = {
+            if new < current {
+                true
+            } else {
+                let delta = new - current;
+                let accept_prob = (-delta / temperature).exp();
+                rng.gen::<f64>() < accept_prob
+            }
+        };
+
+        if accept {
+            // accept candidate
+            for (&(_, k), &e) in incident[i].iter().zip(new_edge_penalties.iter()) {
+                edge_penalties[k] = e;
+            }
+            penalty = new_penalty;
+            n_invalid_edges = new_n_invalid_edges as usize;
+            dislike = new_dislike;
+            stats.accepted_moves += 1;
+        } else {
+            // reject candidate
+            solution[i] = old;
+        }
+
+        if n_invalid_edges == 0 && dislike < best_dislike {
+            best_dislike = dislike;
+            best_solution = solution.clone();
+            stats.record_best(iter, best_dislike);
+            anytime::offer(&best_solution);
+        }
+
+        if let Some(tracer) = tracer.as_mut() {
+            tracer.record_move(accept);
+            if tracer.should_snapshot(iter) {
+                tracer.snapshot(
+                    "penalty_annealing",
+                    iter,
+                    budget.elapsed().as_secs_f64(),
+                    progress,
+                    temperature,
+                    &solution,
+                    &best_solution,
+                    &input.hole,
+                );
+            }
+        }
+    }
+}
+
+// 辺 v-w を p-q に置いたときの違反の大きさと、その辺が妥当か。
+// 伸び縮みは calc_distance_ratio の許容範囲からの超過を元の長さの 2 乗で測る
+fn calc_edge_penalty(input: &Input, v: usize, w: usize, p: &Point, q: &Point) -> (f64, bool) {
+    let original_p = input.figure.vertices[v];
+    let original_q = input.figure.vertices[w];
+    let ratio = calc_distance_ratio(p, q, &original_p, &original_q);
+    let stretch = (ratio.abs() - input.epsilon as f64 / 1000000.0).max(0.0)
+        * squared_distance(&original_p, &original_q);
+    let outside = calc_outside_length(p, q, &input.hole);
+    let ok = is_allowed_distance(p, q, &original_p, &original_q, input.epsilon, false)
+        && outside == 0.0;
+    (stretch + pow2(outside), ok)
+}
+
+// 線分 p-q のうち穴の外にある部分の長さ。穴の辺との交点で区切って、区間の中点で内外を判定する
+fn calc_outside_length(p: &Point, q: &Point, hole: &Polygon) -> f64 {
+    if does_line_fit_in_hole(p, q, hole) {
+        return 0.0;
+    }
+    let d = *q - *p;
+    let mut ts = vec![0.0, 1.0];
+    for line in hole.exterior().lines() {
+        let a = Point::from(line.start);
+        let e = Point::from(line.end) - a;
+        let denom = d.x() * e.y() - d.y() * e.x();
+        if denom.abs() < 1e-12 {
+            continue;
+        }
+        let ap = a - *p;
+        let t = (ap.x() * e.y() - ap.y() * e.x()) / denom;
+        let s = (ap.x() * d.y() - ap.y() * d.x()) / denom;
+        if (0.0..=1.0).contains(&t) && (0.0..=1.0).contains(&s) {
+            ts.push(t);
+        }
+    }
+    ts.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let length = distance(p, q);
+    let mut outside = 0.0;
+    for k in 1..ts.len() {
+        let (t0, t1) = (ts[k - 1], ts[k]);
+        if t1 - t0 < 1e-12 {
+            continue;
+        }
+        let mid = *p + d * ((t0 + t1) / 2.0);
+        if !does_point_fit_in_hole(&mid, hole) {
+            outside += (t1 - t0) * length;
+        }
+    }
+    // 頂点だけをかすめるなど、外に出ている区間が無くても収まっていないことがある
+    outside.max(1e-6)
+}
+
+#[test]
+fn test_calc_outside_length() {
+    // 凹んだ穴 (0,0)-(10,0)-(10,10)-(5,5)-(0,10)
+    let hole = Polygon::new(
+        geo::LineString::from(vec![(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (5.0, 5.0), (0.0, 10.0)]),
+        vec![],
+    );
+    let inside = calc_outside_length(&Point::new(1.0, 1.0), &Point::new(9.0, 1.0), &hole);
+    assert_eq!(inside, 0.0);
+    // (0,8)-(10,8) は x = 2..8 の 6 だけ凹みの外を通る
+    let crossing = calc_outside_length(&Point::new(0.0, 8.0), &Point::new(10.0, 8.0), &hole);
+    assert!((crossing - 6.0).abs() < 1e-9);
+    // 穴の外から中まで
+    let partial = calc_outside_length(&Point::new(-5.0, 1.0), &Point::new(5.0, 1.0), &hole);
+    assert!((partial - 5.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_penalty_annealing_records_only_valid_poses() {
+    let input = crate::inout::parse_input(
+        r#"{"hole":[[0,0],[20,0],[20,20],[0,20]],"epsilon":0,"figure":{"edges":[[0,1],[1,2],[2,3]],"vertices":[[0,0],[6,0],[6,8],[0,8]]},"bonuses":[]}"#,
+    );
+    let run = || {
+        let (solution, dislike, stats) = solve(
+            &input,
+            input.figure.vertices.clone(),
+            &Budget::with_iterations(5000),
+            Some(0),
+            100.0,
+            &Constraints::default(),
+            None,
+        );
+        assert_eq!(stats.iterations, 5000);
+        (solution, dislike)
+    };
+    let (solution, dislike) = run();
+    assert!(does_valid_pose(
+        &solution,
+        &input.figure,
+        &input.hole,
+        input.epsilon,
+        &vec![],
+        None
+    ));
+    assert_eq!(dislike, calculate_dislike(&solution, &input.hole));
+    assert!(dislike <= calculate_dislike(&input.figure.vertices, &input.hole));
+    assert_eq!(run(), (solution, dislike));
+}
+
+#[test]
+fn test_penalty_annealing_starts_from_invalid_pose() {
+    // 辺の長さが 2 倍に伸びた dislike 0 のポーズから始める
+    let input = crate::inout::parse_input(
+        r#"{"hole":[[0,0],[10,0],[0,10]],"epsilon":0,"figure":{"edges":[[0,1],[1,2],[2,0]],"vertices":[[0,0],[5,0],[0,5]]},"bonuses":[]}"#,
+    );
+    let invalid = vec![Point::new(0.0, 0.0), Point::new(10.0, 0.0), Point::new(0.0, 10.0)];
+    assert_eq!(calculate_dislike(&invalid, &input.hole), 0.0);
+    let run = |budget: &Budget| {
+        solve(
+            &input,
+            invalid.clone(),
+            budget,
+            Some(0),
+            100.0,
+            &Constraints::default(),
+            None,
+        )
+    };
+
+    // 妥当なポーズが見つからなければ、渡されたポーズを最良解とせずにそのまま返す
+    let (solution, _, stats) = run(&Budget::with_iterations(1));
+    assert_eq!(solution, invalid);
+    assert_eq!(stats.stop_reason, StopReason::NotFound);
+    assert_eq!(stats.dislike, None);
+
+    let (solution, dislike, stats) = run(&Budget::with_iterations(5000));
+    assert!(does_valid_pose(
+        &solution,
+        &input.figure,
+        &input.hole,
+        input.epsilon,
+        &vec![],
+        None
+    ));
+    assert_eq!(stats.dislike, Some(dislike));
+}
@@ -1,10 +1,12 @@
+use crate::anytime;
+use crate::budget::Budget;
 use crate::common::*;
+use crate::stats::{StageStats, StopReason};
 use geo::contains::Contains;
 use geo::prelude::*;
 use geo::{Closest, Coordinate};
 use rand::prelude::*;
 use std::f64::consts::TAU;
-use std::time::{Duration, Instant};
 
 static SEED: [u8; 32] = [
     0xfd, 0x00, 0xf1, 0x5c, 0xde, 0x01, 0x11, 0xc6, 0xc3, 0xea, 0xfb, 0xbf, 0xf3, 0xca, 0xd8, 0x32,
@@ -18,19 +20,15 @@ fn vec2d(x: f64, y: f64) -> Vector2d {
 }
 
 #[allow(dead_code)]
-pub fn solve(input: &Input, time_limit: Duration, fix_seed: bool) -> (Vec<Point>, f64) {
+pub fn solve(input: &Input, budget: &Budget, seed: Option<u64>) -> (Vec<Point>, f64, StageStats) {
+    let mut stats = StageStats::start("physical");
     let mut solution = input.figure.vertices.clone();
 
     let n = solution.len();
-    let mut rng = if fix_seed {
-        SmallRng::from_seed(SEED)
-    } else {
-        SmallRng::from_entropy()
-    };
+    let mut rng = make_rng(&SEED, seed);
 
     let out_edges = make_out_edges(&input.figure.edges, n);
     let original_vertices = &input.figure.vertices;
-    let start_at = Instant::now();
 
     let mut current_score = calculate_dislike(&solution, &input.hole);
     let mut best_solution = solution.clone();
@@ -54,20 +52,24 @@ pub fn solve(input: &Input, time_limit: Duration, fix_seed: bool) -> (Vec<Point>
         (max_x - min_x).max(max_y - min_y).max(1.0)
     };
 
-    let mut iter = 0;
+    let mut iter: u64 = 0;
     loop {
         // check time limit
         iter += 1;
         if iter % 100 == 0 {
-            let elapsed = Instant::now() - start_at;
-            if best_score == 0.0 || elapsed >= time_limit {
-                eprintln!("iter = {}", iter);
+            if best_score == 0.0 || budget.is_over(iter) {
+                let reason = if best_score == 0.0 {
+                    StopReason::OptimalFound
+                } else {
+                    budget.stop_reason()
+                };
                 let dislike = calculate_dislike(&best_solution, &input.hole);
-                return (best_solution, dislike);
+                let stats = stats.finish(iter, reason, Some(dislike));
+                return (best_solution, dislike, stats);
             }
 
             // tweak temperature
-            progress = elapsed.as_secs_f64() / time_limit.as_secs_f64();
+            progress = budget.progress(iter);
         }
 
         // move
@@ -131,6 +133,8 @@ pub fn solve(input: &Input, time_limit: Duration, fix_seed: bool) -> (Vec<Point>
         if current_score < best_score {
             best_score = current_score;
             best_solution = solution.clone();
+            stats.record_best(iter, best_score);
+            anytime::offer(&best_solution);
         }
     }
 }
@@ -1,5 +1,7 @@
+use crate::budget::Budget;
 use crate::common::*;
 use crate::solvers;
+use crate::stats::{StageStats, StopReason};
 use rand::prelude::*;
 
 static SEED: [u8; 32] = [
@@ -7,7 +9,12 @@ static SEED: [u8; 32] = [
     0x6a, 0xe3, 0x07, 0x99, 0xc5, 0xe0, 0x52, 0xe4, 0xaa, 0x35, 0x07, 0x99, 0xe3, 0x2b, 0x9d, 0xc6,
 ];
 
-pub fn solve(input: &Input, fix_seed: bool) -> Option<(Vec<Point>, f64)> {
+pub fn solve(
+    input: &Input,
+    budget: &Budget,
+    seed: Option<u64>,
+) -> (Option<(Vec<Point>, f64)>, StageStats) {
+    let mut stats = StageStats::start("shrink");
     let big_box = Polygon::new(
         geo::LineString::from(vec![
             Point::new(-1e+9, -1e+9),
@@ -19,11 +26,7 @@ pub fn solve(input: &Input, fix_seed: bool) -> Option<(Vec<Point>, f64)> {
     );
     let mut solution = input.figure.vertices.clone();
     let mut temp_input = input.clone();
-    let mut rng = if fix_seed {
-        SmallRng::from_seed(SEED)
-    } else {
-        SmallRng::from_entropy()
-    };
+    let mut rng = make_rng(&SEED, seed);
     let n = solution.len();
     let mut best_variance = calc_variance(&solution);
 
@@ -33,15 +36,17 @@ pub fn solve(input: &Input, fix_seed: bool) -> Option<(Vec<Point>, f64)> {
         orders[i] = make_determined_order(&out_edges, Some(i));
     }
 
-    let mut move_count = 0;
     for iter in 0..50000 {
+        if iter % 100 == 0 && budget.is_over(iter as u64) {
+            return (None, stats.finish(iter as u64, budget.stop_reason(), None));
+        }
         if iter % (n * 10) == 0 {
             let temp = temp_input.figure.vertices;
             temp_input.figure.vertices = solution.clone();
-            let ret = solvers::orthgonal::solve(&temp_input);
+            let (ret, _) = solvers::orthgonal::solve(&temp_input, budget);
             temp_input.figure.vertices = temp;
-            if ret.is_some() {
-                return ret;
+            if let Some((_, dislike)) = ret {
+                return (ret, stats.finish(iter as u64, StopReason::Completed, Some(dislike)));
             }
             // eprintln!("orthgonal is failed: {} {}", iter, move_count);
         }
@@ -56,7 +61,8 @@ pub fn solve(input: &Input, fix_seed: bool) -> Option<(Vec<Point>, f64)> {
         let temp = temp_input.hole;
         temp_input.hole = big_box.clone();
         let next_solution =
-            fix_allowed_distance_violation(from, &solution, &temp_input, &out_edges, &orders);
+            fix_allowed_distance_violation(from, &solution, &temp_input, &out_edges, &orders, None)
+                .into_valid();
         temp_input.hole = temp;
         solution[from] = old;
         if next_solution.is_none() {
@@ -69,10 +75,10 @@ pub fn solve(input: &Input, fix_seed: bool) -> Option<(Vec<Point>, f64)> {
             // eprintln!("move success: {} {} {}", iter, dx, dy);
             solution = next_solution;
             best_variance = variance;
-            move_count += 1;
+            stats.accepted_moves += 1;
         }
     }
-    return None;
+    return (None, stats.finish(50000, StopReason::NotFound, None));
 }
 
 fn calc_variance(solution: &Vec<Point>) -> f64 {
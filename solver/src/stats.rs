@@ -0,0 +1,128 @@
+use serde::Serialize;
+use web_time::Instant;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StopReason {
+    // 探索すべきものを全て調べ終えた
+    Completed,
+    TimeLimit,
+    // 反復回数の予算を使い切った
+    IterationLimit,
+    // SIGINT/SIGTERM で中断された
+    Interrupted,
+    // dislike = 0 の解が見つかった
+    OptimalFound,
+    // 解が見つからなかった
+    NotFound,
+    // 前段で解が得られなかったなどの理由で実行しなかった
+    Skipped,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct DislikeRecord {
+    pub elapsed: f64,
+    pub iter: u64,
+    pub dislike: f64,
+}
+
+// 各ソルバーが返す実行結果の統計
+#[derive(Debug, Clone, Serialize)]
+pub struct StageStats {
+    pub stage: String,
+    pub iterations: u64,
+    pub accepted_moves: u64,
+    pub best_dislike_history: Vec<DislikeRecord>,
+    pub elapsed: f64,
+    pub stop_reason: StopReason,
+    pub dislike: Option<f64>,
+    #[serde(skip)]
+    start_at: Option<Instant>,
+}
+
+impl StageStats {
+    pub fn start(stage: &str) -> StageStats {
+        StageStats {
+            stage: stage.to_string(),
+            iterations: 0,
+            accepted_moves: 0,
+            best_dislike_history: vec![],
+            elapsed: 0.0,
+            stop_reason: StopReason::Completed,
+            dislike: None,
+            start_at: Some(Instant::now()),
+        }
+    }
+
+    pub fn skipped(stage: &str) -> StageStats {
+        let mut stats = StageStats::start(stage);
+        stats.start_at = None;
+        stats.stop_reason = StopReason::Skipped;
+        stats
+    }
+
+    fn elapsed_secs(&self) -> f64 {
+        self.start_at
+            .map(|t| (Instant::now() - t).as_secs_f64())
+            .unwrap_or(0.0)
+    }
+
+    // これまでの最良値より真に小さいときだけ記録する
+    pub fn record_best(&mut self, iter: u64, dislike: f64) {
+        if let Some(last) = self.best_dislike_history.last() {
+            if last.dislike <= dislike {
+                return;
+            }
+        }
+        let elapsed = self.elapsed_secs();
+        self.best_dislike_history.push(DislikeRecord {
+            elapsed,
+            iter,
+            dislike,
+        });
+    }
+
+    pub fn finish(
+        mut self,
+        iterations: u64,
+        stop_reason: StopReason,
+        dislike: Option<f64>,
+    ) -> StageStats {
+        self.iterations = iterations;
+        self.elapsed = self.elapsed_secs();
+        self.stop_reason = stop_reason;
+        self.dislike = dislike;
+        if let Some(d) = dislike {
+            self.record_best(iterations, d);
+        }
+        self
+    }
+}
+
+// パイプライン全体の実行結果
+#[derive(Debug, Clone, Serialize)]
+pub struct PipelineSummary {
+    pub stages: Vec<StageStats>,
+    pub dislike: Option<f64>,
+    pub valid: bool,
+    pub interrupted: bool,
+    pub elapsed: f64,
+}
+
+#[test]
+fn test_record_best() {
+    let mut stats = StageStats::start("test");
+    stats.record_best(1, 10.0);
+    stats.record_best(2, 10.0);
+    stats.record_best(3, 12.0);
+    stats.record_best(4, 8.0);
+    let stats = stats.finish(5, StopReason::TimeLimit, Some(8.0));
+    let dislikes: Vec<f64> = stats
+        .best_dislike_history
+        .iter()
+        .map(|r| r.dislike)
+        .collect();
+    assert_eq!(dislikes, vec![10.0, 8.0]);
+    assert_eq!(stats.iterations, 5);
+    assert_eq!(stats.stop_reason, StopReason::TimeLimit);
+}
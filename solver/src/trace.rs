@@ -0,0 +1,310 @@
+use crate::common::*;
+use crate::render;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write as _;
+use std::io::{BufRead, BufWriter, Write};
+use std::path::Path;
+
+// アニメーションの 1 フレームの長さ (秒)
+const FRAME_SECONDS: f64 = 0.1;
+
+// 探索途中のある時点での状態
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub solver: String,
+    pub iter: u64,
+    pub elapsed: f64,
+    pub progress: f64,
+    pub temperature: f64,
+    pub acceptance_rate: f64,
+    pub current_dislike: f64,
+    pub best_dislike: f64,
+    pub current: Vec<Vec<i64>>,
+    pub best: Vec<Vec<i64>>,
+}
+
+// 局所探索の途中経過を JSONL で書き出す
+pub struct Tracer {
+    writer: BufWriter<std::fs::File>,
+    interval: u64,
+    tried: u64,
+    accepted: u64,
+}
+
+fn to_json_vertices(vertices: &[Point]) -> Vec<Vec<i64>> {
+    vertices
+        .iter()
+        .map(|p| vec![p.x() as i64, p.y() as i64])
+        .collect()
+}
+
+fn from_json_vertices(vertices: &[Vec<i64>]) -> Vec<Point> {
+    vertices
+        .iter()
+        .map(|p| Point::new(p[0] as f64, p[1] as f64))
+        .collect()
+}
+
+impl Tracer {
+    pub fn create(path: &Path, interval: u64) -> Tracer {
+        assert!(interval > 0, "trace interval must be positive");
+        let file = std::fs::File::create(path)
+            .unwrap_or_else(|e| panic!("can't create {}: {}", path.display(), e));
+        Tracer {
+            writer: BufWriter::new(file),
+            interval,
+            tried: 0,
+            accepted: 0,
+        }
+    }
+
+    // 近傍への移動を試みるたびに呼ぶ
+    pub fn record_move(&mut self, accepted: bool) {
+        self.tried += 1;
+        if accepted {
+            self.accepted += 1;
+        }
+    }
+
+    pub fn should_snapshot(&self, iter: u64) -> bool {
+        iter.is_multiple_of(self.interval)
+    }
+
+    // 前回のスナップショットからの受理率も記録し、カウンタはリセットする
+    #[allow(clippy::too_many_arguments)]
+    pub fn snapshot(
+        &mut self,
+        solver: &str,
+        iter: u64,
+        elapsed: f64,
+        progress: f64,
+        temperature: f64,
+        current: &[Point],
+        best: &[Point],
+        hole: &Polygon,
+    ) {
+        let acceptance_rate = if self.tried == 0 {
+            0.0
+        } else {
+            self.accepted as f64 / self.tried as f64
+        };
+        let snapshot = Snapshot {
+            solver: solver.to_string(),
+            iter,
+            elapsed,
+            progress,
+            temperature,
+            acceptance_rate,
+            current_dislike: calculate_dislike(current, hole),
+            best_dislike: calculate_dislike(best, hole),
+            current: to_json_vertices(current),
+            best: to_json_vertices(best),
+        };
+        serde_json::to_writer(&mut self.writer, &snapshot).unwrap();
+        self.writer.write_all(b"\n").unwrap();
+        self.writer.flush().unwrap();
+        self.tried = 0;
+        self.accepted = 0;
+    }
+}
+
+pub fn load_trace(path: &Path) -> Vec<Snapshot> {
+    let file = std::fs::File::open(path)
+        .unwrap_or_else(|e| panic!("can't open {}: {}", path.display(), e));
+    std::io::BufReader::new(file)
+        .lines()
+        .map(|line| line.unwrap_or_else(|e| panic!("can't load {}: {}", path.display(), e)))
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str(&line).expect("failed to parse trace as JSON"))
+        .collect()
+}
+
+fn snapshot_title(s: &Snapshot) -> String {
+    format!(
+        "{} iter={} t={:.2}s progress={:.3} temperature={:.3} accept={:.3} dislike={} best={}",
+        s.solver,
+        s.iter,
+        s.elapsed,
+        s.progress,
+        s.temperature,
+        s.acceptance_rate,
+        s.current_dislike,
+        s.best_dislike
+    )
+}
+
+// 現在のポーズを辺の伸び縮みで色付けし、最良のポーズを細線で重ねる
+pub fn make_frame(input: &Input, snapshot: &Snapshot) -> render::Scene {
+    let current = from_json_vertices(&snapshot.current);
+    let best = from_json_vertices(&snapshot.best);
+    let mut scene = render::make_scene(input, Some(&current));
+    scene.include_points(&best);
+    let unit = scene.unit();
+    for e in input.figure.edges.iter() {
+        scene.shapes.push(render::Shape::Line {
+            from: best[e.v],
+            to: best[e.w],
+            color: render::Color::rgba(0x00, 0x00, 0x00, 0xa0),
+            width: unit,
+            dashed: false,
+        });
+    }
+    scene.title = snapshot_title(snapshot);
+    scene
+}
+
+fn discrete_animate(attribute: &str, values: &[String], duration: f64) -> String {
+    format!(
+        r#"<animate attributeName="{}" values="{}" dur="{}s" calcMode="discrete" repeatCount="indefinite"/>"#,
+        attribute,
+        values.join(";"),
+        duration
+    )
+}
+
+// 全スナップショットを 1 枚のアニメーション SVG にまとめる
+pub fn make_animated_svg(input: &Input, snapshots: &[Snapshot]) -> String {
+    let currents: Vec<Vec<Point>> = snapshots
+        .iter()
+        .map(|s| from_json_vertices(&s.current))
+        .collect();
+    let bests: Vec<Vec<Point>> = snapshots
+        .iter()
+        .map(|s| from_json_vertices(&s.best))
+        .collect();
+    let mut scene = render::make_scene(input, None);
+    for pose in currents.iter().chain(bests.iter()) {
+        scene.include_points(pose);
+    }
+    let unit = scene.unit();
+    let duration = FRAME_SECONDS * snapshots.len().max(1) as f64;
+
+    let mut svg = render::svg_header(&scene);
+    for shape in scene.shapes.iter() {
+        writeln!(svg, "{}", render::shape_to_svg(shape)).unwrap();
+    }
+    if snapshots.is_empty() {
+        svg.push_str("</svg>\n");
+        return svg;
+    }
+
+    for (poses, is_current) in [(&currents, true), (&bests, false)].iter() {
+        let (width, color) = if *is_current {
+            (unit * 3.0, render::Color::rgb(0x00, 0xa0, 0x30))
+        } else {
+            (unit, render::Color::rgba(0x00, 0x00, 0x00, 0xa0))
+        };
+        for e in input.figure.edges.iter() {
+            let p1 = poses[0][e.v];
+            let p2 = poses[0][e.w];
+            writeln!(
+                svg,
+                r#"<line x1="{}" y1="{}" x2="{}" y2="{}" stroke="{}" stroke-opacity="{}" stroke-width="{}" stroke-linecap="round">"#,
+                p1.x(),
+                p1.y(),
+                p2.x(),
+                p2.y(),
+                color.to_svg(),
+                color.opacity(),
+                width
+            )
+            .unwrap();
+            let xs = |i: usize| -> Vec<String> {
+                poses
+                    .iter()
+                    .map(|pose| format!("{}", pose[i].x()))
+                    .collect()
+            };
+            let ys = |i: usize| -> Vec<String> {
+                poses
+                    .iter()
+                    .map(|pose| format!("{}", pose[i].y()))
+                    .collect()
+            };
+            writeln!(svg, "{}", discrete_animate("x1", &xs(e.v), duration)).unwrap();
+            writeln!(svg, "{}", discrete_animate("y1", &ys(e.v), duration)).unwrap();
+            writeln!(svg, "{}", discrete_animate("x2", &xs(e.w), duration)).unwrap();
+            writeln!(svg, "{}", discrete_animate("y2", &ys(e.w), duration)).unwrap();
+            if *is_current {
+                let colors: Vec<String> = poses
+                    .iter()
+                    .map(|pose| render::pose_edge_style(input, pose, e).0.to_svg())
+                    .collect();
+                writeln!(svg, "{}", discrete_animate("stroke", &colors, duration)).unwrap();
+            }
+            svg.push_str("</line>\n");
+        }
+    }
+
+    // フレームごとの情報は、そのフレームの間だけ表示されるテキストにする
+    let font_size = unit * 14.0;
+    let n = snapshots.len() as f64;
+    for (k, s) in snapshots.iter().enumerate() {
+        let (values, key_times) = if k == 0 {
+            ("1;0".to_string(), format!("0;{}", 1.0 / n))
+        } else {
+            (
+                "0;1;0".to_string(),
+                format!("0;{};{}", k as f64 / n, (k + 1) as f64 / n),
+            )
+        };
+        writeln!(
+            svg,
+            r#"<text x="{}" y="{}" font-size="{}" font-family="monospace" opacity="{}">{}<animate attributeName="opacity" values="{}" keyTimes="{}" dur="{}s" calcMode="discrete" repeatCount="indefinite"/></text>"#,
+            scene.min.x() + font_size * 0.5,
+            scene.min.y() + font_size * 1.2,
+            font_size,
+            if k == 0 { 1 } else { 0 },
+            snapshot_title(s),
+            values,
+            key_times,
+            duration
+        )
+        .unwrap();
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+#[test]
+fn test_trace_round_trip() {
+    let input = crate::inout::parse_input(
+        r#"{"hole":[[0,0],[20,0],[20,20],[0,20]],"epsilon":0,"figure":{"edges":[[0,1],[1,2]],"vertices":[[0,0],[4,0],[4,3]]},"bonuses":[]}"#,
+    );
+    let path = std::env::temp_dir().join(format!("trace-test-{}.jsonl", std::process::id()));
+    let mut tracer = Tracer::create(&path, 10);
+    let mut pose = input.figure.vertices.clone();
+    let best = pose.clone();
+    for iter in 0..30 {
+        tracer.record_move(iter % 2 == 0);
+        if tracer.should_snapshot(iter) {
+            tracer.snapshot("test", iter, 0.0, 0.0, 1.0, &pose, &best, &input.hole);
+        }
+        pose[2] = Point::new(4.0, 3.0 + (iter % 4) as f64);
+    }
+    drop(tracer);
+
+    let snapshots = load_trace(&path);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(
+        snapshots.iter().map(|s| s.iter).collect::<Vec<_>>(),
+        vec![0, 10, 20]
+    );
+    // 受理率はスナップショットごとにリセットされる
+    assert_eq!(snapshots[0].acceptance_rate, 1.0);
+    assert_eq!(snapshots[1].acceptance_rate, 0.5);
+    assert_eq!(snapshots[2].acceptance_rate, 0.5);
+    assert_eq!(snapshots[1].current[2], vec![4, 4]);
+    assert_eq!(snapshots[1].best, snapshots[0].best);
+
+    let svg = make_animated_svg(&input, &snapshots);
+    assert!(svg.starts_with("<svg"));
+    assert!(svg.ends_with("</svg>\n"));
+    // 現在と最良のポーズの辺それぞれに座標 4 つ、現在の辺には色のアニメーションも付く。
+    // 情報のテキストはフレームごとに 1 つ
+    assert_eq!(svg.matches("</line>").count(), 4);
+    assert_eq!(svg.matches("<animate ").count(), 2 * 5 + 2 * 4 + 3);
+    assert_eq!(svg.matches("<text ").count(), 3);
+    assert!(svg.contains(r#"values="4;4;4""#));
+    assert!(svg.contains(r#"values="3;4;6""#));
+}
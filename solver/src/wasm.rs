@@ -0,0 +1,156 @@
+use crate::audit::parse_bonus_type;
+use crate::budget::Budget;
+use crate::common::*;
+use crate::constraints::Constraints;
+use crate::inout::*;
+use crate::solvers;
+use serde::Serialize;
+use std::time::Duration;
+use wasm_bindgen::prelude::*;
+
+// 問題とポーズは提出するときと同じ JSON の文字列で受け取り、結果も JSON の文字列で返す
+
+fn load(problem: &str, pose: &str) -> Result<(Input, PoseJSON), JsValue> {
+    let input = try_parse_input(problem).map_err(|e| JsValue::from_str(&e))?;
+    let pose_json: PoseJSON =
+        serde_json::from_str(pose).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    Ok((input, pose_json))
+}
+
+// ポーズの頂点数が図形と合っていなければ、検証の途中で添字が範囲外になるのでここで弾く
+fn load_vertices(problem: &str, pose: &str) -> Result<(Input, PoseJSON, Vec<Point>), JsValue> {
+    let (input, pose_json) = load(problem, pose)?;
+    let vertices = pose_json_to_vertices(&pose_json);
+    if vertices.len() < input.figure.vertices.len() {
+        return Err(JsValue::from_str(&format!(
+            "pose has {} vertices, but the figure has {}",
+            vertices.len(),
+            input.figure.vertices.len()
+        )));
+    }
+    Ok((input, pose_json, vertices))
+}
+
+#[derive(Debug, Serialize)]
+struct ValidationJSON {
+    valid: bool,
+    dislike: f64,
+    // (辺の番号, 長さの比のずれ)。ボーナスは考慮しない
+    stretched_edges: Vec<(usize, f64)>,
+    outside_edges: Vec<usize>,
+}
+
+// ポーズの JSON の bonuses から、検証に使うボーナスと折る辺を取り出す
+fn used_bonuses(
+    pose_json: &PoseJSON,
+    input: &Input,
+) -> Result<(Vec<BonusType>, Option<Edge>), JsValue> {
+    let mut used_bonus_types = vec![];
+    let mut break_leg = None;
+    for b in pose_json.bonuses.iter().flatten() {
+        let bonus_type = parse_bonus_type(&b.bonus)
+            .ok_or_else(|| JsValue::from_str(&format!("unsupported bonus {}", b.bonus)))?;
+        // 折る辺は問題の向きに揃える
+        if bonus_type == BonusType::BreakALeg {
+            let edge = b
+                .edge
+                .as_ref()
+                .filter(|e| e.len() == 2)
+                .and_then(|e| {
+                    input.figure.edges.iter().copied().find(|f| {
+                        (f.v as i64, f.w as i64) == (e[0], e[1])
+                            || (f.w as i64, f.v as i64) == (e[0], e[1])
+                    })
+                })
+                .ok_or_else(|| JsValue::from_str("BREAK_A_LEG needs an edge of the figure"))?;
+            break_leg = Some(edge);
+        }
+        used_bonus_types.push(bonus_type);
+    }
+    Ok((used_bonus_types, break_leg))
+}
+
+// ポーズがボーナスも含めて妥当かどうかと、どの辺が制約を満たしていないかを返す
+#[wasm_bindgen]
+pub fn validate_pose(problem: &str, pose: &str) -> Result<String, JsValue> {
+    let (input, pose_json, vertices) = load_vertices(problem, pose)?;
+    let (used_bonus_types, break_leg) = used_bonuses(&pose_json, &input)?;
+    let expected = input.figure.vertices.len() + if break_leg.is_some() { 1 } else { 0 };
+    let valid = vertices.len() == expected
+        && does_valid_pose(
+            &vertices,
+            &input.figure,
+            &input.hole,
+            input.epsilon,
+            &used_bonus_types,
+            break_leg,
+        );
+    let report = make_pose_report(&vertices[..input.figure.vertices.len()], &input);
+    let j = ValidationJSON {
+        valid,
+        dislike: calculate_dislike(&vertices, &input.hole),
+        stretched_edges: report.stretched_edges,
+        outside_edges: report.outside_edges,
+    };
+    Ok(serde_json::to_string(&j).unwrap())
+}
+
+#[wasm_bindgen]
+pub fn dislike(problem: &str, pose: &str) -> Result<f64, JsValue> {
+    let (input, pose_json) = load(problem, pose)?;
+    Ok(calculate_dislike(
+        &pose_json_to_vertices(&pose_json),
+        &input.hole,
+    ))
+}
+
+// vertex を動かした後、周りの辺の長さが合うように他の頂点を動かす。直せなければ undefined を返す
+#[wasm_bindgen]
+pub fn fix_allowed_distance_violation(
+    problem: &str,
+    pose: &str,
+    vertex: usize,
+) -> Result<Option<String>, JsValue> {
+    let (input, _, vertices) = load_vertices(problem, pose)?;
+    let n = input.figure.vertices.len();
+    if vertex >= n {
+        return Err(JsValue::from_str(&format!(
+            "vertex {} is out of range",
+            vertex
+        )));
+    }
+    let out_edges = make_out_edges(&input.figure.edges, n);
+    let orders: Vec<Vec<usize>> = (0..n)
+        .map(|i| make_determined_order(&out_edges, Some(i)))
+        .collect();
+    let vertices = vertices[..n].to_vec();
+    Ok(crate::common::fix_allowed_distance_violation(
+        vertex, &vertices, &input, &out_edges, &orders, None,
+    )
+    .into_valid()
+    .map(|fixed| vertices_to_pose_json(&fixed, &vec![], &None)))
+}
+
+// time_limit_millis ミリ秒だけ焼きなましを行い、結果のポーズを返す
+#[wasm_bindgen]
+pub fn anneal(
+    problem: &str,
+    pose: &str,
+    time_limit_millis: f64,
+    initial_temperature: f64,
+) -> Result<String, JsValue> {
+    let (input, _, vertices) = load_vertices(problem, pose)?;
+    let n = input.figure.vertices.len();
+    let budget = Budget::new(Duration::from_secs_f64(time_limit_millis.max(0.0) / 1000.0));
+    let (solution, _, _) = solvers::annealing::solve(
+        &input,
+        vertices[..n].to_vec(),
+        &budget,
+        None,
+        initial_temperature,
+        0.0,
+        &Constraints::default(),
+        None,
+    );
+    Ok(vertices_to_pose_json(&solution, &vec![], &None))
+}